@@ -0,0 +1,57 @@
+use amia_base64::Base64;
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+use std::io::{self, Write};
+
+const BASE64_TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn encode_per_sextet<W: Write>(data: &[u8], writer: &mut W) -> io::Result<()> {
+    data.chunks(3)
+        .map(|chunk| {
+            let (b0, b1, b2) = (
+                chunk.first().copied().unwrap_or(0),
+                chunk.get(1).copied().unwrap_or(0),
+                chunk.get(2).copied().unwrap_or(0),
+            );
+            let n = ((b0 as u32) << 16) | ((b1 as u32) << 8) | (b2 as u32);
+            [
+                BASE64_TABLE[((n >> 18) & 0x3F) as usize],
+                BASE64_TABLE[((n >> 12) & 0x3F) as usize],
+                if chunk.len() > 1 {
+                    BASE64_TABLE[((n >> 6) & 0x3F) as usize]
+                } else {
+                    b'='
+                },
+                if chunk.len() > 2 {
+                    BASE64_TABLE[(n & 0x3F) as usize]
+                } else {
+                    b'='
+                },
+            ]
+        })
+        .try_for_each(|buf| writer.write_all(&buf))
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    for size in [32usize, 1024, 65536] {
+        let data = (0..size).map(|i| (i * 31) as u8).collect::<Vec<_>>();
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("per_sextet", size), &data, |b, data| {
+            b.iter(|| {
+                let mut out = Vec::with_capacity(4 * data.len().div_ceil(3));
+                encode_per_sextet(black_box(data), &mut out).unwrap();
+                out
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("pair_table", size), &data, |b, data| {
+            b.iter(|| black_box(data).encode_base64().unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_encode);
+criterion_main!(benches);
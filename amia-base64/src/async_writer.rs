@@ -0,0 +1,159 @@
+use crate::encode_to_writer;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use tokio::io::AsyncWrite;
+
+// Input accepted per poll_write, so the encoded backlog stays bounded.
+const MAX_CHUNK: usize = 3 * 1024;
+
+pub struct AsyncBase64Writer<W> {
+    inner: W,
+    pending: [u8; 2],
+    pending_len: usize,
+    output: Vec<u8>,
+    written: usize,
+    finished: bool,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncBase64Writer<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            pending: [0; 2],
+            pending_len: 0,
+            output: Vec::new(),
+            written: 0,
+            finished: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.output.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.output[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.output.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncBase64Writer<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.finished {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Base64 writer already shut down",
+            )));
+        }
+        ready!(this.poll_drain(cx))?;
+
+        let accepted = buf.len().min(MAX_CHUNK);
+        let mut rest = &buf[..accepted];
+
+        // Complete the carried-over group on the stack before encoding `buf` in place.
+        if this.pending_len > 0 {
+            let take = (3 - this.pending_len).min(rest.len());
+            let filled = this.pending_len + take;
+            let mut group = [0u8; 3];
+            group[..this.pending_len].copy_from_slice(&this.pending[..this.pending_len]);
+            group[this.pending_len..filled].copy_from_slice(&rest[..take]);
+            rest = &rest[take..];
+
+            if filled < 3 {
+                this.pending[..filled].copy_from_slice(&group[..filled]);
+                this.pending_len = filled;
+                return Poll::Ready(Ok(accepted));
+            }
+            encode_to_writer(&group, &mut this.output)?;
+        }
+
+        let whole = rest.len() / 3 * 3;
+        encode_to_writer(&rest[..whole], &mut this.output)?;
+        this.pending_len = rest.len() - whole;
+        this.pending[..this.pending_len].copy_from_slice(&rest[whole..]);
+
+        Poll::Ready(Ok(accepted))
+    }
+
+    // A partial group stays buffered until shutdown, since padding it would end the stream.
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.finished {
+            encode_to_writer(&this.pending[..this.pending_len], &mut this.output)?;
+            this.pending_len = 0;
+            this.finished = true;
+        }
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Base64;
+    use std::task::Waker;
+
+    // `Vec<u8>` never returns `Pending`, so a no-op waker is enough to drive the writer.
+    fn write_in_chunks(data: &[u8], chunk: usize) -> Vec<u8> {
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut writer = AsyncBase64Writer::new(Vec::new());
+        for part in data.chunks(chunk) {
+            let mut part = part;
+            while !part.is_empty() {
+                let Poll::Ready(n) = Pin::new(&mut writer).poll_write(&mut cx, part) else {
+                    unreachable!()
+                };
+                part = &part[n.unwrap()..];
+            }
+        }
+        assert!(matches!(
+            Pin::new(&mut writer).poll_shutdown(&mut cx),
+            Poll::Ready(Ok(()))
+        ));
+        writer.into_inner()
+    }
+
+    #[test]
+    fn chunked_writes_match_one_shot_encoding() {
+        let data: Vec<u8> = (0..=255).cycle().take(MAX_CHUNK + 10).collect();
+        for len in [0, 1, 2, 3, 4, 5, 100, MAX_CHUNK + 10] {
+            let input = &data[..len];
+            let expected = input.encode_base64().unwrap();
+            for chunk in [1, 2, 4, 5, 7, MAX_CHUNK + 10] {
+                let encoded = write_in_chunks(input, chunk);
+                assert_eq!(
+                    String::from_utf8(encoded).unwrap(),
+                    expected,
+                    "len {} chunk {}",
+                    len,
+                    chunk
+                );
+            }
+        }
+    }
+}
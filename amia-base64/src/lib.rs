@@ -1,8 +1,16 @@
+#[cfg(feature = "tokio")]
+mod async_writer;
+
+#[cfg(feature = "tokio")]
+pub use async_writer::AsyncBase64Writer;
+
 use std::io::{self, Write};
 
 const BASE64_TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
 const INVALID: u8 = 255;
 
+const _: () = assert!(BASE64_TABLE.len() == 64);
+
 const fn build_reverse_table() -> [u8; 256] {
     let mut table = [INVALID; 256];
     let mut i = 0;
@@ -15,111 +23,638 @@ const fn build_reverse_table() -> [u8; 256] {
 
 const REVERSE_BASE64_TABLE: [u8; 256] = build_reverse_table();
 
+const fn build_pair_table() -> [[u8; 2]; 4096] {
+    let mut table = [[0u8; 2]; 4096];
+    let mut i = 0;
+    while i < 4096 {
+        table[i] = [BASE64_TABLE[i >> 6], BASE64_TABLE[i & 0x3F]];
+        i += 1;
+    }
+    table
+}
+
+static BASE64_PAIR_TABLE: [[u8; 2]; 4096] = build_pair_table();
+
 pub trait Base64 {
     fn encode_base64(&self) -> io::Result<String>;
+    fn encode_base64_url_nopad(&self) -> io::Result<String>;
     fn decode_base64(&self) -> io::Result<Vec<u8>>;
+    fn decode_base64_to_string(&self) -> io::Result<String>;
+    /// Decodes straight into `writer`, skipping `\r` and `\n`. On invalid input, the
+    /// groups before the error may already have been written.
+    fn decode_base64_to_writer<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()>;
+    fn decode_base64_ignoring(&self, ignore: &[u8]) -> io::Result<Vec<u8>>;
+    fn decode_base64_limited(&self, max_output: usize) -> io::Result<Vec<u8>>;
+    fn try_decode_base64(&self) -> Result<Vec<u8>, Base64Error>;
+    /// Lenient decode for tooling such as log scraping, not for protocol data. Invalid
+    /// bytes and malformed groups are dropped and decoding resumes at the next Base64
+    /// character. Returns the decoded bytes and the number of skipped regions.
+    fn decode_base64_salvage(&self) -> (Vec<u8>, usize);
+    fn encode_base64_with_pad(&self, pad: u8) -> io::Result<String>;
+    fn decode_base64_with_pad(&self, pad: u8) -> io::Result<Vec<u8>>;
+    /// Encodes into a stack buffer. `N` must be at least `4 * input_len.div_ceil(3)`
+    /// (24 for a 16-byte token, 44 for 32 bytes); a smaller `N` panics.
+    #[cfg(feature = "arrayvec")]
+    fn encode_base64_stack<const N: usize>(&self) -> arrayvec::ArrayString<N>;
 }
 
-fn encode_to_writer<W: Write>(data: &[u8], writer: &mut W) -> io::Result<()> {
-    data.chunks(3)
-        .map(|chunk| {
-            let (b0, b1, b2) = (
-                chunk.get(0).copied().unwrap_or(0),
-                chunk.get(1).copied().unwrap_or(0),
-                chunk.get(2).copied().unwrap_or(0),
-            );
-            let n = ((b0 as u32) << 16) | ((b1 as u32) << 8) | (b2 as u32);
-            let output = [
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Base64Error {
+    InvalidLength,
+    InvalidCharacter { byte: u8, offset: usize },
+    InvalidPadding,
+}
+
+impl std::fmt::Display for Base64Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Base64Error::InvalidLength => write!(f, "Invalid Base64 length"),
+            Base64Error::InvalidCharacter { byte, offset } => {
+                write!(
+                    f,
+                    "Invalid Base64 character 0x{:02X} at offset {}",
+                    byte, offset
+                )
+            }
+            Base64Error::InvalidPadding => write!(f, "Invalid Base64 padding"),
+        }
+    }
+}
+
+impl std::error::Error for Base64Error {}
+
+impl From<Base64Error> for io::Error {
+    fn from(err: Base64Error) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+pub struct Base64Encode<I> {
+    inner: I,
+    output: [u8; 4],
+    position: usize,
+}
+
+impl<I: Iterator<Item = u8>> Base64Encode<I> {
+    pub fn new(inner: I) -> Self {
+        Self {
+            inner,
+            output: [0; 4],
+            position: 4,
+        }
+    }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for Base64Encode<I> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position == 4 {
+            let b0 = self.inner.next()?;
+            let b1 = self.inner.next();
+            let b2 = b1.and_then(|_| self.inner.next());
+
+            let n =
+                ((b0 as u32) << 16) | ((b1.unwrap_or(0) as u32) << 8) | (b2.unwrap_or(0) as u32);
+            self.output = [
                 BASE64_TABLE[((n >> 18) & 0x3F) as usize],
                 BASE64_TABLE[((n >> 12) & 0x3F) as usize],
-                if chunk.len() > 1 {
+                if b1.is_some() {
                     BASE64_TABLE[((n >> 6) & 0x3F) as usize]
                 } else {
                     b'='
                 },
-                if chunk.len() > 2 {
+                if b2.is_some() {
                     BASE64_TABLE[(n & 0x3F) as usize]
                 } else {
                     b'='
                 },
             ];
-            output
+            self.position = 0;
+        }
+
+        let c = self.output[self.position] as char;
+        self.position += 1;
+        Some(c)
+    }
+}
+
+fn encode_to_writer<W: Write>(data: &[u8], writer: &mut W) -> io::Result<()> {
+    let chunks = data.chunks_exact(3);
+    let remainder = chunks.remainder();
+
+    chunks
+        .map(|chunk| {
+            let n = ((chunk[0] as usize) << 16) | ((chunk[1] as usize) << 8) | (chunk[2] as usize);
+            let [c0, c1] = BASE64_PAIR_TABLE[n >> 12];
+            let [c2, c3] = BASE64_PAIR_TABLE[n & 0xFFF];
+            [c0, c1, c2, c3]
         })
-        .try_for_each(|buf| writer.write_all(&buf))
+        .try_for_each(|buf| writer.write_all(&buf))?;
+
+    if remainder.is_empty() {
+        return Ok(());
+    }
+
+    let b0 = remainder[0];
+    let b1 = remainder.get(1).copied().unwrap_or(0);
+    let n = ((b0 as u32) << 16) | ((b1 as u32) << 8);
+    writer.write_all(&[
+        BASE64_TABLE[((n >> 18) & 0x3F) as usize],
+        BASE64_TABLE[((n >> 12) & 0x3F) as usize],
+        if remainder.len() > 1 {
+            BASE64_TABLE[((n >> 6) & 0x3F) as usize]
+        } else {
+            b'='
+        },
+        b'=',
+    ])
 }
 
-fn decode_to_writer<W: Write>(input: &[u8], writer: &mut W) -> io::Result<()> {
-    if input.len() % 4 != 0 {
+/// Encodes the concatenation of `segments` without first copying them together;
+/// 3-byte groups may span segment boundaries.
+pub fn encode_base64_chained(segments: &[&[u8]]) -> io::Result<String> {
+    let total: usize = segments.iter().map(|s| s.len()).sum();
+    let mut result = Vec::with_capacity(4 * total.div_ceil(3));
+    let mut carry = [0u8; 3];
+    let mut carry_len = 0;
+
+    for &segment in segments {
+        let mut rest = segment;
+        if carry_len > 0 {
+            let (head, tail) = rest.split_at((3 - carry_len).min(rest.len()));
+            carry[carry_len..carry_len + head.len()].copy_from_slice(head);
+            carry_len += head.len();
+            if carry_len < 3 {
+                continue;
+            }
+            encode_to_writer(&carry, &mut result)?;
+            rest = tail;
+        }
+
+        let whole = rest.len() - rest.len() % 3;
+        encode_to_writer(&rest[..whole], &mut result)?;
+        carry_len = rest.len() - whole;
+        carry[..carry_len].copy_from_slice(&rest[whole..]);
+    }
+
+    encode_to_writer(&carry[..carry_len], &mut result)?;
+    Ok(String::from_utf8(result).unwrap())
+}
+
+fn decode_quartet(
+    chunk: &[u8],
+    pad: u8,
+    offset: usize,
+    last: bool,
+) -> Result<([u8; 3], usize), Base64Error> {
+    let value = |i: usize| match REVERSE_BASE64_TABLE[chunk[i] as usize] {
+        INVALID => Err(Base64Error::InvalidCharacter {
+            byte: chunk[i],
+            offset: offset + i,
+        }),
+        v => Ok(v),
+    };
+
+    // At most two trailing pads: `A===` and `====` carry too little data for a byte.
+    if chunk[0] == pad || chunk[1] == pad {
+        return Err(Base64Error::InvalidPadding);
+    }
+
+    let v0 = value(0)?;
+    let v1 = value(1)?;
+    let (v2, v3, len) = match (chunk[2] == pad, chunk[3] == pad) {
+        (true, true) => (0, 0, 1),
+        (true, false) => return Err(Base64Error::InvalidPadding),
+        (false, true) => (value(2)?, 0, 2),
+        (false, false) => (value(2)?, value(3)?, 3),
+    };
+
+    if len < 3 && !last {
+        return Err(Base64Error::InvalidPadding);
+    }
+
+    Ok((
+        [(v0 << 2) | (v1 >> 4), (v1 << 4) | (v2 >> 2), (v2 << 6) | v3],
+        len,
+    ))
+}
+
+fn check_pad(pad: u8) -> io::Result<()> {
+    if !pad.is_ascii_graphic() || REVERSE_BASE64_TABLE[pad as usize] != INVALID {
         return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "Invalid Base64 length",
+            io::ErrorKind::InvalidInput,
+            "Pad character must be printable ASCII outside the Base64 alphabet",
         ));
     }
+    Ok(())
+}
 
-    for chunk in input.chunks(4) {
-        let c0 = chunk[0];
-        let c1 = chunk[1];
-        let c2 = chunk[2];
-        let c3 = chunk[3];
+fn decode_to_vec(input: &[u8], pad: u8, output: &mut Vec<u8>) -> Result<(), Base64Error> {
+    if !input.len().is_multiple_of(4) {
+        return Err(Base64Error::InvalidLength);
+    }
 
-        let v0 = REVERSE_BASE64_TABLE[c0 as usize];
-        let v1 = REVERSE_BASE64_TABLE[c1 as usize];
-        let v2 = if c2 != b'=' {
-            REVERSE_BASE64_TABLE[c2 as usize]
-        } else {
-            0
-        };
-        let v3 = if c3 != b'=' {
-            REVERSE_BASE64_TABLE[c3 as usize]
-        } else {
-            0
-        };
+    let count = input.len() / 4;
+    for (i, chunk) in input.chunks_exact(4).enumerate() {
+        let (bytes, len) = decode_quartet(chunk, pad, i * 4, i + 1 == count)?;
+        output.extend_from_slice(&bytes[..len]);
+    }
 
-        if v0 == INVALID
-            || v1 == INVALID
-            || (c2 != b'=' && v2 == INVALID)
-            || (c3 != b'=' && v3 == INVALID)
-        {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Invalid Base64 character",
-            ));
+    Ok(())
+}
+
+fn decode_to_writer<W: Write + ?Sized>(input: &[u8], writer: &mut W) -> io::Result<()> {
+    let is_data = |b: &u8| *b != b'\r' && *b != b'\n';
+    let len = input.iter().filter(|b| is_data(b)).count();
+    if !len.is_multiple_of(4) {
+        return Err(Base64Error::InvalidLength.into());
+    }
+
+    let count = len / 4;
+    let mut data = input.iter().enumerate().filter(|(_, b)| is_data(b));
+    let mut output = [0u8; 768];
+    let mut filled = 0;
+
+    for i in 0..count {
+        let mut chunk = [0u8; 4];
+        let mut offsets = [0usize; 4];
+        for (slot, (offset, &byte)) in data.by_ref().take(4).enumerate() {
+            chunk[slot] = byte;
+            offsets[slot] = offset;
+        }
+
+        let (bytes, len) =
+            decode_quartet(&chunk, b'=', 0, i + 1 == count).map_err(|err| match err {
+                Base64Error::InvalidCharacter { byte, offset } => Base64Error::InvalidCharacter {
+                    byte,
+                    offset: offsets[offset],
+                },
+                err => err,
+            })?;
+
+        output[filled..filled + len].copy_from_slice(&bytes[..len]);
+        filled += len;
+        if filled == output.len() {
+            writer.write_all(&output)?;
+            filled = 0;
+        }
+    }
+
+    writer.write_all(&output[..filled])
+}
+
+fn decode_salvage(input: &[u8]) -> (Vec<u8>, usize) {
+    let mut output = Vec::with_capacity(input.len() / 4 * 3);
+    let mut group = [0u8; 4];
+    let mut len = 0;
+    let mut skipped = 0;
+    let mut in_garbage = false;
+
+    for &byte in input {
+        if byte == b'\r' || byte == b'\n' {
+            continue;
         }
 
-        let b0 = (v0 << 2) | (v1 >> 4);
-        writer.write_all(&[b0])?;
+        if byte != b'=' && REVERSE_BASE64_TABLE[byte as usize] == INVALID {
+            // Adjacent bad bytes, and the partial group they cut off, form one region.
+            if !in_garbage {
+                skipped += 1;
+                in_garbage = true;
+            }
+            len = 0;
+            continue;
+        }
 
-        if c2 != b'=' {
-            let b1 = (v1 << 4) | (v2 >> 2);
-            writer.write_all(&[b1])?;
+        in_garbage = false;
+        group[len] = byte;
+        len += 1;
+        if len < 4 {
+            continue;
         }
 
-        if c3 != b'=' {
-            let b2 = (v2 << 6) | v3;
-            writer.write_all(&[b2])?;
+        len = 0;
+        // Padded groups are accepted mid-input, since a log may hold several blobs.
+        match decode_quartet(&group, b'=', 0, true) {
+            Ok((bytes, n)) => output.extend_from_slice(&bytes[..n]),
+            Err(_) => {
+                skipped += 1;
+                in_garbage = true;
+            }
         }
     }
 
-    Ok(())
+    if len > 0 {
+        skipped += 1;
+    }
+
+    (output, skipped)
+}
+
+fn try_decode_ignoring(input: &[u8], ignore: &[u8], pad: u8) -> Result<Vec<u8>, Base64Error> {
+    let filtered = input
+        .iter()
+        .copied()
+        .filter(|b| !ignore.contains(b))
+        .collect::<Vec<_>>();
+    let mut output = Vec::with_capacity(filtered.len() / 4 * 3);
+    decode_to_vec(&filtered, pad, &mut output).map_err(|err| match err {
+        Base64Error::InvalidCharacter { byte, offset } => Base64Error::InvalidCharacter {
+            byte,
+            offset: input
+                .iter()
+                .enumerate()
+                .filter(|(_, b)| !ignore.contains(b))
+                .nth(offset)
+                .map_or(offset, |(i, _)| i),
+        },
+        err => err,
+    })?;
+    Ok(output)
 }
 
 impl<T: AsRef<[u8]>> Base64 for T {
     fn encode_base64(&self) -> io::Result<String> {
         let data = self.as_ref();
-        let mut result = Vec::with_capacity(4 * ((data.len() + 2) / 3));
+        let mut result = Vec::with_capacity(4 * data.len().div_ceil(3));
         encode_to_writer(data, &mut result)?;
         Ok(String::from_utf8(result).unwrap())
     }
 
+    fn encode_base64_url_nopad(&self) -> io::Result<String> {
+        let data = self.as_ref();
+        let mut result = Vec::with_capacity(4 * data.len().div_ceil(3));
+        encode_to_writer(data, &mut result)?;
+        while result.last() == Some(&b'=') {
+            result.pop();
+        }
+        result.iter_mut().for_each(|b| match *b {
+            b'+' => *b = b'-',
+            b'/' => *b = b'_',
+            _ => {}
+        });
+        Ok(String::from_utf8(result).unwrap())
+    }
+
     fn decode_base64(&self) -> io::Result<Vec<u8>> {
+        self.decode_base64_ignoring(b"\r\n")
+    }
+
+    fn decode_base64_to_string(&self) -> io::Result<String> {
+        let output = self.decode_base64()?;
+        String::from_utf8(output).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn decode_base64_to_writer<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        decode_to_writer(self.as_ref(), writer)
+    }
+
+    fn decode_base64_ignoring(&self, ignore: &[u8]) -> io::Result<Vec<u8>> {
+        if ignore
+            .iter()
+            .any(|&b| b == b'=' || REVERSE_BASE64_TABLE[b as usize] != INVALID)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Ignore set overlaps the Base64 alphabet or padding",
+            ));
+        }
+
+        Ok(try_decode_ignoring(self.as_ref(), ignore, b'=')?)
+    }
+    fn decode_base64_limited(&self, max_output: usize) -> io::Result<Vec<u8>> {
+        let is_data = |b: &&u8| **b != b'\r' && **b != b'\n';
         let input = self.as_ref();
-        let filtered = input
+        let len = input.iter().filter(is_data).count();
+        let padding = input
             .iter()
-            .copied()
-            .filter(|&b| b != b'\r' && b != b'\n')
-            .collect::<Vec<_>>();
-        let mut output = Vec::with_capacity(filtered.len() / 4 * 3);
-        decode_to_writer(&filtered, &mut output)?;
-        Ok(output)
+            .rev()
+            .filter(is_data)
+            .take(2)
+            .filter(|&&b| b == b'=')
+            .count();
+
+        let decoded_len = (len / 4 * 3).saturating_sub(padding);
+        if decoded_len > max_output {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Decoded Base64 length exceeds limit",
+            ));
+        }
+
+        self.decode_base64()
+    }
+
+    fn try_decode_base64(&self) -> Result<Vec<u8>, Base64Error> {
+        try_decode_ignoring(self.as_ref(), b"\r\n", b'=')
+    }
+
+    fn decode_base64_salvage(&self) -> (Vec<u8>, usize) {
+        decode_salvage(self.as_ref())
+    }
+
+    fn encode_base64_with_pad(&self, pad: u8) -> io::Result<String> {
+        check_pad(pad)?;
+        let mut result = self.encode_base64()?.into_bytes();
+        result
+            .iter_mut()
+            .rev()
+            .take(2)
+            .filter(|b| **b == b'=')
+            .for_each(|b| *b = pad);
+        Ok(String::from_utf8(result).unwrap())
+    }
+
+    fn decode_base64_with_pad(&self, pad: u8) -> io::Result<Vec<u8>> {
+        check_pad(pad)?;
+        Ok(try_decode_ignoring(self.as_ref(), b"\r\n", pad)?)
+    }
+
+    #[cfg(feature = "arrayvec")]
+    fn encode_base64_stack<const N: usize>(&self) -> arrayvec::ArrayString<N> {
+        let data = self.as_ref();
+        let encoded_len = 4 * data.len().div_ceil(3);
+        assert!(
+            encoded_len <= N,
+            "encode_base64_stack: N = {} is too small for {} encoded bytes",
+            N,
+            encoded_len
+        );
+
+        let mut result = arrayvec::ArrayVec::<u8, N>::new();
+        encode_to_writer(data, &mut result).unwrap();
+        arrayvec::ArrayString::from(std::str::from_utf8(&result).unwrap()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Differential check against the `base64` crate over pseudo-random inputs of every
+    // length up to a few blocks, covering all three padding cases.
+    #[test]
+    fn matches_base64_crate() {
+        use base64::Engine as _;
+        use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        for len in 0..=300 {
+            let input: Vec<u8> = (0..len)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect();
+
+            let expected = STANDARD.encode(&input);
+            assert_eq!(input.encode_base64().unwrap(), expected, "len {}", len);
+            assert_eq!(
+                input.encode_base64_url_nopad().unwrap(),
+                URL_SAFE_NO_PAD.encode(&input),
+                "len {}",
+                len
+            );
+            assert_eq!(expected.decode_base64().unwrap(), input, "len {}", len);
+        }
+    }
+
+    // Header and payload segments of the jwt.io example token and the RFC 7515 §3.3 header.
+    #[test]
+    fn url_nopad_matches_jwt_segments() {
+        let vectors: [(&[u8], &str); 5] = [
+            (
+                br#"{"alg":"HS256","typ":"JWT"}"#,
+                "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9",
+            ),
+            (
+                br#"{"sub":"1234567890","name":"John Doe","iat":1516239022}"#,
+                "eyJzdWIiOiIxMjM0NTY3ODkwIiwibmFtZSI6IkpvaG4gRG9lIiwiaWF0IjoxNTE2MjM5MDIyfQ",
+            ),
+            (
+                b"{\"typ\":\"JWT\",\r\n \"alg\":\"HS256\"}",
+                "eyJ0eXAiOiJKV1QiLA0KICJhbGciOiJIUzI1NiJ9",
+            ),
+            (&[0xFB, 0xFF], "-_8"),
+            (&[0xFB, 0xFF, 0xBF], "-_-_"),
+        ];
+        for (input, expected) in vectors {
+            assert_eq!(input.encode_base64_url_nopad().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn decode_quartet_rejects_misplaced_padding() {
+        for chunk in [b"A===", b"====", b"=ABC", b"AB=C"] {
+            assert_eq!(
+                decode_quartet(chunk, b'=', 0, true),
+                Err(Base64Error::InvalidPadding),
+                "{:?}",
+                std::str::from_utf8(chunk).unwrap()
+            );
+        }
+
+        assert_eq!(
+            decode_quartet(b"QQ==", b'=', 0, true),
+            Ok(([b'A', 0, 0], 1))
+        );
+        assert_eq!(
+            decode_quartet(b"QQ==", b'=', 0, false),
+            Err(Base64Error::InvalidPadding)
+        );
+        assert_eq!(
+            "QQ==QUJD".try_decode_base64(),
+            Err(Base64Error::InvalidPadding)
+        );
+        assert_eq!("QUJDQQ==".try_decode_base64(), Ok(b"ABCA".to_vec()));
+    }
+
+    #[test]
+    fn custom_pad_round_trips_and_rejects_collisions() {
+        for input in [&b"A"[..], b"AB", b"ABC"] {
+            let encoded = input.encode_base64_with_pad(b'.').unwrap();
+            assert_eq!(encoded.decode_base64_with_pad(b'.').unwrap(), input);
+        }
+        assert_eq!(b"A".encode_base64_with_pad(b'.').unwrap(), "QQ..");
+
+        // With a custom pad, `=` is just another byte outside the alphabet.
+        let err = "QQ==".decode_base64_with_pad(b'.').unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        for pad in [b'A', b'z', b'0', b'+', b'/'] {
+            let err = b"A".encode_base64_with_pad(pad).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            let err = "QQ==".decode_base64_with_pad(pad).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    struct CountingWriter {
+        bytes: usize,
+        writes: usize,
+    }
+
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.bytes += buf.len();
+            self.writes += 1;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn decode_to_writer_matches_decode_across_flush_boundary() {
+        // 768 bytes fill the stack buffer exactly; 769 spill one byte into a second write.
+        for len in [0, 1, 767, 768, 769, 1536, 2000] {
+            let input: Vec<u8> = (0..len).map(|i| (i * 7) as u8).collect();
+            let encoded = input.encode_base64().unwrap();
+
+            let mut decoded = Vec::new();
+            encoded.decode_base64_to_writer(&mut decoded).unwrap();
+            assert_eq!(decoded, input, "len {}", len);
+
+            let mut counter = CountingWriter {
+                bytes: 0,
+                writes: 0,
+            };
+            encoded.decode_base64_to_writer(&mut counter).unwrap();
+            assert_eq!(counter.bytes, len, "len {}", len);
+            assert_eq!(counter.writes, len.div_ceil(768), "len {}", len);
+        }
+    }
+
+    #[test]
+    fn decode_to_writer_reports_offsets_in_original_input() {
+        let mut decoded = Vec::new();
+        let err = "QUJD\r\nQU*D"
+            .decode_base64_to_writer(&mut decoded)
+            .unwrap_err();
+        let err = err.into_inner().unwrap().downcast::<Base64Error>().unwrap();
+        assert_eq!(
+            *err,
+            Base64Error::InvalidCharacter {
+                byte: b'*',
+                offset: 8
+            }
+        );
+    }
+
+    #[test]
+    fn salvage_skips_garbage_and_partial_groups() {
+        assert_eq!(
+            "QUJD####QUJD".decode_base64_salvage(),
+            (b"ABCABC".to_vec(), 1)
+        );
+        assert_eq!("QUJDQU".decode_base64_salvage(), (b"ABC".to_vec(), 1));
+        assert_eq!(
+            "QUJD\r\nQQ==".decode_base64_salvage(),
+            (b"ABCA".to_vec(), 0)
+        );
     }
 }
@@ -1,23 +1,80 @@
+pub mod magic;
 pub mod net_packet;
 
 #[cfg(feature = "kcp")]
 pub mod net_operation;
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ByteSnippet {
+    bytes: [u8; ByteSnippet::CAPACITY],
+    len: u8,
+}
+
+impl ByteSnippet {
+    pub const CAPACITY: usize = 20;
+
+    pub fn new(data: &[u8]) -> Self {
+        let len = data.len().min(Self::CAPACITY);
+        let mut bytes = [0u8; Self::CAPACITY];
+        bytes[..len].copy_from_slice(&data[..len]);
+        Self {
+            bytes,
+            len: len as u8,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+impl std::fmt::Display for ByteSnippet {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, byte) in self.as_bytes().iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for ByteSnippet {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "[{}]", self)
+    }
+}
+
 #[derive(Debug)]
 pub enum PacketError {
     TooShort,
-    InvalidHeadMagic,
-    InvalidTailMagic,
+    InvalidHeadMagic(ByteSnippet),
+    InvalidTailMagic(ByteSnippet),
     SizeMismatch,
+    FrameTooLarge { len: usize, max: usize },
+    Io(std::io::Error),
 }
 
 impl std::fmt::Display for PacketError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            PacketError::InvalidHeadMagic(bytes) => write!(f, "InvalidHeadMagic [{}]", bytes),
+            PacketError::InvalidTailMagic(bytes) => write!(f, "InvalidTailMagic [{}]", bytes),
+            PacketError::Io(err) => write!(f, "Io {}", err),
+            _ => write!(f, "{:?}", self),
+        }
     }
 }
 
-impl std::error::Error for PacketError {}
+impl std::error::Error for PacketError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PacketError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
 
 #[cfg(feature = "tokio")]
 impl From<PacketError> for std::io::Error {
@@ -25,8 +82,9 @@ impl From<PacketError> for std::io::Error {
         use PacketError::*;
         let kind = match err {
             TooShort => std::io::ErrorKind::UnexpectedEof,
-            SizeMismatch => std::io::ErrorKind::InvalidData,
-            InvalidHeadMagic | InvalidTailMagic => std::io::ErrorKind::InvalidData,
+            SizeMismatch | FrameTooLarge { .. } => std::io::ErrorKind::InvalidData,
+            InvalidHeadMagic(_) | InvalidTailMagic(_) => std::io::ErrorKind::InvalidData,
+            Io(err) => return err,
         };
         std::io::Error::new(kind, err)
     }
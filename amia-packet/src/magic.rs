@@ -0,0 +1,14 @@
+pub const HEAD_MAGIC: [u8; 4] = [0x9D, 0x74, 0xC7, 0x14];
+pub const TAIL_MAGIC: [u8; 4] = [0xD7, 0xA1, 0x52, 0xC8];
+
+pub const HEAD_MAGIC_U32: u32 = u32::from_be_bytes(HEAD_MAGIC);
+pub const TAIL_MAGIC_U32: u32 = u32::from_be_bytes(TAIL_MAGIC);
+
+pub const CONNECT_HEAD: u32 = 0xFF;
+pub const CONNECT_TAIL: u32 = 0xFFFFFFFF;
+
+pub const ESTABLISH_HEAD: u32 = 0x145;
+pub const ESTABLISH_TAIL: u32 = 0x14514545;
+
+pub const DISCONNECT_HEAD: u32 = 0x194;
+pub const DISCONNECT_TAIL: u32 = 0x19419494;
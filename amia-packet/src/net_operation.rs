@@ -1,7 +1,11 @@
-use crate::PacketError;
+use crate::magic::{
+    CONNECT_HEAD, CONNECT_TAIL, DISCONNECT_HEAD, DISCONNECT_TAIL, ESTABLISH_HEAD, ESTABLISH_TAIL,
+};
+use crate::{ByteSnippet, PacketError};
 use byteorder::{BE, ByteOrder};
+use std::io::Read;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct NetOperation {
     pub head: u32,
     pub conv: u32,
@@ -10,32 +14,121 @@ pub struct NetOperation {
     pub tail: u32,
 }
 
+impl NetOperation {
+    const fn with_magic(head: u32, tail: u32, conv: u32, token: u32) -> Self {
+        Self {
+            head,
+            conv,
+            token,
+            data: 0,
+            tail,
+        }
+    }
+
+    pub const fn connect_request(conv: u32, token: u32) -> Self {
+        Self::with_magic(CONNECT_HEAD, CONNECT_TAIL, conv, token)
+    }
+
+    pub const fn connect_ack(conv: u32, token: u32) -> Self {
+        Self::with_magic(ESTABLISH_HEAD, ESTABLISH_TAIL, conv, token)
+    }
+
+    pub const fn disconnect(conv: u32, token: u32) -> Self {
+        Self::with_magic(DISCONNECT_HEAD, DISCONNECT_TAIL, conv, token)
+    }
+
+    pub const fn from_be_words(words: [u32; 5]) -> Self {
+        Self {
+            head: u32::from_be(words[0]),
+            conv: u32::from_be(words[1]),
+            token: u32::from_be(words[2]),
+            data: u32::from_be(words[3]),
+            tail: u32::from_be(words[4]),
+        }
+    }
+
+    pub const fn to_be_words(&self) -> [u32; 5] {
+        [
+            self.head.to_be(),
+            self.conv.to_be(),
+            self.token.to_be(),
+            self.data.to_be(),
+            self.tail.to_be(),
+        ]
+    }
+
+    /// Checks that `head` is one of the connect, establish or disconnect magics and that
+    /// `tail` is the one paired with it.
+    pub fn validate(&self) -> Result<(), PacketError> {
+        let expected_tail = match self.head {
+            CONNECT_HEAD => CONNECT_TAIL,
+            ESTABLISH_HEAD => ESTABLISH_TAIL,
+            DISCONNECT_HEAD => DISCONNECT_TAIL,
+            head => {
+                return Err(PacketError::InvalidHeadMagic(ByteSnippet::new(
+                    &head.to_be_bytes(),
+                )));
+            }
+        };
+        if self.tail != expected_tail {
+            return Err(PacketError::InvalidTailMagic(ByteSnippet::new(
+                &self.tail.to_be_bytes(),
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn write_into(&self, buf: &mut [u8; 20]) {
+        BE::write_u32(&mut buf[0..4], self.head);
+        BE::write_u32(&mut buf[4..8], self.conv);
+        BE::write_u32(&mut buf[8..12], self.token);
+        BE::write_u32(&mut buf[12..16], self.data);
+        BE::write_u32(&mut buf[16..20], self.tail);
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self, PacketError> {
+        let mut buf = [0u8; 20];
+        reader
+            .read_exact(&mut buf)
+            .map_err(|err| match err.kind() {
+                std::io::ErrorKind::UnexpectedEof => PacketError::TooShort,
+                _ => PacketError::Io(err),
+            })?;
+        Ok(Self::from(&buf))
+    }
+}
+
 impl TryFrom<&[u8]> for NetOperation {
     type Error = PacketError;
 
     fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
-        if bytes.len() != 20 {
-            return Err(PacketError::SizeMismatch);
-        }
+        let bytes: &[u8; 20] = bytes.try_into().map_err(|_| PacketError::SizeMismatch)?;
+        Ok(Self::from(bytes))
+    }
+}
 
-        Ok(Self {
+impl From<&[u8; 20]> for NetOperation {
+    fn from(bytes: &[u8; 20]) -> Self {
+        Self {
             head: BE::read_u32(&bytes[0..4]),
             conv: BE::read_u32(&bytes[4..8]),
             token: BE::read_u32(&bytes[8..12]),
             data: BE::read_u32(&bytes[12..16]),
             tail: BE::read_u32(&bytes[16..20]),
-        })
+        }
+    }
+}
+
+impl From<[u8; 20]> for NetOperation {
+    fn from(bytes: [u8; 20]) -> Self {
+        Self::from(&bytes)
     }
 }
 
 impl From<NetOperation> for [u8; 20] {
     fn from(op: NetOperation) -> Self {
         let mut buf = [0u8; 20];
-        BE::write_u32(&mut buf[0..4], op.head);
-        BE::write_u32(&mut buf[4..8], op.conv);
-        BE::write_u32(&mut buf[8..12], op.token);
-        BE::write_u32(&mut buf[12..16], op.data);
-        BE::write_u32(&mut buf[16..20], op.tail);
+        op.write_into(&mut buf);
         buf
     }
 }
@@ -51,3 +144,59 @@ impl From<NetOperation> for Vec<u8> {
         <[u8; 20]>::from(op).to_vec()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FailingReader;
+
+    impl Read for FailingReader {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::ConnectionReset.into())
+        }
+    }
+
+    #[test]
+    fn read_from_short_input_is_too_short() {
+        let bytes: [u8; 20] = NetOperation::connect_request(1, 2).into();
+        let err = NetOperation::read_from(&mut &bytes[..19]).unwrap_err();
+        assert!(matches!(err, PacketError::TooShort));
+    }
+
+    #[test]
+    fn validate_accepts_known_magic_pairs() {
+        for op in [
+            NetOperation::connect_request(1, 2),
+            NetOperation::connect_ack(1, 2),
+            NetOperation::disconnect(1, 2),
+        ] {
+            assert!(op.validate().is_ok(), "{:?}", op);
+        }
+    }
+
+    #[test]
+    fn validate_rejects_unknown_head_and_mismatched_tail() {
+        let mut op = NetOperation::connect_request(1, 2);
+        op.head = 0x1234;
+        assert!(matches!(
+            op.validate(),
+            Err(PacketError::InvalidHeadMagic(_))
+        ));
+
+        let mut op = NetOperation::connect_request(1, 2);
+        op.tail = ESTABLISH_TAIL;
+        assert!(matches!(
+            op.validate(),
+            Err(PacketError::InvalidTailMagic(_))
+        ));
+    }
+
+    #[test]
+    fn read_from_propagates_other_io_errors() {
+        let err = NetOperation::read_from(&mut FailingReader).unwrap_err();
+        assert!(
+            matches!(&err, PacketError::Io(io) if io.kind() == std::io::ErrorKind::ConnectionReset)
+        );
+    }
+}
@@ -1,15 +1,25 @@
-use crate::PacketError;
+use crate::{ByteSnippet, PacketError};
+
+#[cfg(feature = "tokio")]
+use crate::magic::{HEAD_MAGIC_U32, TAIL_MAGIC_U32};
 
 #[cfg(feature = "tokio")]
 use tokio::io::AsyncReadExt;
 #[cfg(feature = "tokio")]
 use tokio::io::AsyncWriteExt;
 
+#[cfg(feature = "proto3")]
+use amia_proto3::{DecodeResult, Protobuf};
+
+#[cfg(feature = "codec")]
+use bytes::{BufMut, BytesMut};
+#[cfg(feature = "codec")]
+use tokio_util::codec::{Decoder, Encoder};
+
 use byteorder::{BE, ByteOrder};
 use std::mem::size_of;
 
-pub const HEAD_MAGIC: [u8; 4] = [0x9D, 0x74, 0xC7, 0x14];
-pub const TAIL_MAGIC: [u8; 4] = [0xD7, 0xA1, 0x52, 0xC8];
+pub use crate::magic::{HEAD_MAGIC, TAIL_MAGIC};
 
 const HEAD_MAGIC_LEN: usize = size_of::<u32>();
 const CMD_LEN: usize = size_of::<u16>();
@@ -41,9 +51,6 @@ pub struct NetPacket {
 impl NetPacket {
     #[cfg(feature = "tokio")]
     pub async fn write(&self, stream: &mut (impl AsyncWriteExt + Unpin)) -> std::io::Result<()> {
-        const HEAD_MAGIC_U32: u32 = 0x9D74C714;
-        const TAIL_MAGIC_U32: u32 = 0xD7A152C8;
-
         stream.write_u32(HEAD_MAGIC_U32).await?;
         stream.write_u16(self.cmd).await?;
         stream.write_u16(self.head.len() as u16).await?;
@@ -56,12 +63,11 @@ impl NetPacket {
 
     #[cfg(feature = "tokio")]
     pub async fn read(stream: &mut (impl AsyncReadExt + Unpin)) -> std::io::Result<Self> {
-        const HEAD_MAGIC_U32: u32 = 0x9D74C714;
-        const TAIL_MAGIC_U32: u32 = 0xD7A152C8;
-
         let head_magic = stream.read_u32().await?;
         if head_magic != HEAD_MAGIC_U32 {
-            return Err(PacketError::InvalidHeadMagic.into());
+            return Err(
+                PacketError::InvalidHeadMagic(ByteSnippet::new(&head_magic.to_be_bytes())).into(),
+            );
         }
 
         let cmd = stream.read_u16().await?;
@@ -77,13 +83,193 @@ impl NetPacket {
 
         let tail_magic = stream.read_u32().await?;
         if tail_magic != TAIL_MAGIC_U32 {
-            return Err(PacketError::InvalidTailMagic.into());
+            return Err(
+                PacketError::InvalidTailMagic(ByteSnippet::new(&tail_magic.to_be_bytes())).into(),
+            );
         }
 
         Ok(Self { cmd, head, body })
     }
 }
 
+// Which message types belong to which `cmd` is left to the caller.
+#[cfg(feature = "proto3")]
+impl NetPacket {
+    pub fn from_proto(cmd: u16, head: &impl Protobuf, body: &impl Protobuf) -> Self {
+        Self {
+            cmd,
+            head: head.encode_to_vec(),
+            body: body.encode_to_vec(),
+        }
+    }
+
+    pub fn decode_head<M: Protobuf>(&self) -> DecodeResult<M> {
+        M::decode_from_slice(&self.head)
+    }
+
+    pub fn decode_body<M: Protobuf>(&self) -> DecodeResult<M> {
+        M::decode_from_slice(&self.body)
+    }
+}
+
+/// Largest frame, magics and length fields included, that [`NetPacketReader`] and
+/// `NetPacketCodec` accept unless configured otherwise.
+#[cfg(feature = "tokio")]
+pub const DEFAULT_MAX_FRAME_LEN: usize = 8 * 1024 * 1024;
+
+#[cfg(feature = "tokio")]
+pub struct NetPacketReader<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    max_frame_len: usize,
+}
+
+#[cfg(feature = "tokio")]
+impl<R: AsyncReadExt + Unpin> NetPacketReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_max_frame_len(reader, DEFAULT_MAX_FRAME_LEN)
+    }
+
+    /// Frames whose declared length exceeds `max_frame_len` fail with `InvalidData` as soon
+    /// as their header arrives, before the body is buffered.
+    pub fn with_max_frame_len(reader: R, max_frame_len: usize) -> Self {
+        Self {
+            reader,
+            buffer: Vec::new(),
+            max_frame_len,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    pub async fn read_packet(&mut self) -> std::io::Result<Option<NetPacket>> {
+        loop {
+            if let Some(frame_len) = frame_len(&self.buffer, self.max_frame_len)? {
+                let packet = NetPacket::try_from(&self.buffer[..frame_len])?;
+                self.buffer.drain(..frame_len);
+                return Ok(Some(packet));
+            }
+
+            let mut chunk = [0u8; 4096];
+            let n = self.reader.read(&mut chunk).await?;
+            if n == 0 {
+                if self.buffer.is_empty() {
+                    return Ok(None);
+                }
+                return Err(PacketError::TooShort.into());
+            }
+            self.buffer.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+// Length of the complete frame at the start of `buffer`, or `None` until one has arrived.
+#[cfg(feature = "tokio")]
+fn frame_len(buffer: &[u8], max_frame_len: usize) -> Result<Option<usize>, PacketError> {
+    if buffer.len() >= HM_END && buffer[HM_START..HM_END] != HEAD_MAGIC {
+        return Err(PacketError::InvalidHeadMagic(ByteSnippet::new(buffer)));
+    }
+
+    if buffer.len() < BS_END {
+        return Ok(None);
+    }
+
+    let head_len = BE::read_u16(&buffer[HS_START..HS_END]) as usize;
+    let body_len = BE::read_u32(&buffer[BS_START..BS_END]) as usize;
+    let frame_len = OVERHEAD + head_len + body_len;
+
+    if frame_len > max_frame_len {
+        return Err(PacketError::FrameTooLarge {
+            len: frame_len,
+            max: max_frame_len,
+        });
+    }
+
+    if buffer.len() < frame_len {
+        return Ok(None);
+    }
+
+    Ok(Some(frame_len))
+}
+
+/// `tokio_util` codec for [`NetPacket`] frames, for use with `Framed`, `FramedRead` and
+/// `FramedWrite`. Decoding yields `Ok(None)` until a whole frame is buffered.
+#[cfg(feature = "codec")]
+#[derive(Debug, Clone, Copy)]
+pub struct NetPacketCodec {
+    max_frame_len: usize,
+}
+
+#[cfg(feature = "codec")]
+impl NetPacketCodec {
+    pub fn new() -> Self {
+        Self::with_max_frame_len(DEFAULT_MAX_FRAME_LEN)
+    }
+
+    /// Frames whose declared length exceeds `max_frame_len` fail with `InvalidData` as soon
+    /// as their header is buffered.
+    pub fn with_max_frame_len(max_frame_len: usize) -> Self {
+        Self { max_frame_len }
+    }
+
+    pub fn max_frame_len(&self) -> usize {
+        self.max_frame_len
+    }
+}
+
+#[cfg(feature = "codec")]
+impl Default for NetPacketCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "codec")]
+impl Decoder for NetPacketCodec {
+    type Item = NetPacket;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> std::io::Result<Option<NetPacket>> {
+        let Some(frame_len) = frame_len(src, self.max_frame_len)? else {
+            return Ok(None);
+        };
+        let frame = src.split_to(frame_len);
+        Ok(Some(NetPacket::try_from(&frame[..])?))
+    }
+}
+
+#[cfg(feature = "codec")]
+impl Encoder<NetPacket> for NetPacketCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, packet: NetPacket, dst: &mut BytesMut) -> std::io::Result<()> {
+        let head_len = u16::try_from(packet.head.len()).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Packet head exceeds u16 length",
+            )
+        })?;
+        let body_len = u32::try_from(packet.body.len()).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Packet body exceeds u32 length",
+            )
+        })?;
+
+        dst.reserve(OVERHEAD + packet.head.len() + packet.body.len());
+        dst.put_slice(&HEAD_MAGIC);
+        dst.put_u16(packet.cmd);
+        dst.put_u16(head_len);
+        dst.put_u32(body_len);
+        dst.put_slice(&packet.head);
+        dst.put_slice(&packet.body);
+        dst.put_slice(&TAIL_MAGIC);
+        Ok(())
+    }
+}
+
 impl TryFrom<&[u8]> for NetPacket {
     type Error = PacketError;
 
@@ -92,8 +278,8 @@ impl TryFrom<&[u8]> for NetPacket {
             return Err(PacketError::TooShort);
         }
 
-        if &data[HM_START..HM_END] != HEAD_MAGIC {
-            return Err(PacketError::InvalidHeadMagic);
+        if data[HM_START..HM_END] != HEAD_MAGIC {
+            return Err(PacketError::InvalidHeadMagic(ByteSnippet::new(data)));
         }
 
         let cmd = BE::read_u16(&data[CMD_START..CMD_END]);
@@ -119,8 +305,10 @@ impl TryFrom<&[u8]> for NetPacket {
             return Err(PacketError::SizeMismatch);
         }
 
-        if &data[tail_start..tail_end] != TAIL_MAGIC {
-            return Err(PacketError::InvalidTailMagic);
+        if data[tail_start..tail_end] != TAIL_MAGIC {
+            return Err(PacketError::InvalidTailMagic(ByteSnippet::new(
+                &data[tail_start..tail_end],
+            )));
         }
 
         Ok(NetPacket {
@@ -139,7 +327,7 @@ impl From<NetPacket> for Box<[u8]> {
         let total_len = OVERHEAD + head_len + body_len;
         let mut out = vec![0u8; total_len];
 
-        (&mut out[HM_START..HM_END]).copy_from_slice(&HEAD_MAGIC);
+        out[HM_START..HM_END].copy_from_slice(&HEAD_MAGIC);
         BE::write_u16(&mut out[CMD_START..CMD_END], value.cmd);
         BE::write_u16(&mut out[HS_START..HS_END], head_len as u16);
         BE::write_u32(&mut out[BS_START..BS_END], body_len as u32);
@@ -153,9 +341,9 @@ impl From<NetPacket> for Box<[u8]> {
         let tail_start = body_end;
         let tail_end = tail_start + TAIL_MAGIC_LEN;
 
-        (&mut out[head_start..head_end]).copy_from_slice(&value.head);
-        (&mut out[body_start..body_end]).copy_from_slice(&value.body);
-        (&mut out[tail_start..tail_end]).copy_from_slice(&TAIL_MAGIC);
+        out[head_start..head_end].copy_from_slice(&value.head);
+        out[body_start..body_end].copy_from_slice(&value.body);
+        out[tail_start..tail_end].copy_from_slice(&TAIL_MAGIC);
 
         out.into_boxed_slice()
     }
@@ -169,7 +357,7 @@ impl From<NetPacket> for Vec<u8> {
         let total_len = OVERHEAD + head_len + body_len;
         let mut out = vec![0u8; total_len];
 
-        (&mut out[HM_START..HM_END]).copy_from_slice(&HEAD_MAGIC);
+        out[HM_START..HM_END].copy_from_slice(&HEAD_MAGIC);
         BE::write_u16(&mut out[CMD_START..CMD_END], value.cmd);
         BE::write_u16(&mut out[HS_START..HS_END], head_len as u16);
         BE::write_u32(&mut out[BS_START..BS_END], body_len as u32);
@@ -183,10 +371,168 @@ impl From<NetPacket> for Vec<u8> {
         let tail_start = body_end;
         let tail_end = tail_start + TAIL_MAGIC_LEN;
 
-        (&mut out[head_start..head_end]).copy_from_slice(&value.head);
-        (&mut out[body_start..body_end]).copy_from_slice(&value.body);
-        (&mut out[tail_start..tail_end]).copy_from_slice(&TAIL_MAGIC);
+        out[head_start..head_end].copy_from_slice(&value.head);
+        out[body_start..body_end].copy_from_slice(&value.body);
+        out[tail_start..tail_end].copy_from_slice(&TAIL_MAGIC);
 
         out
     }
 }
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use super::*;
+
+    fn frame(cmd: u16, head: &[u8], body: &[u8]) -> Vec<u8> {
+        let packet = NetPacket {
+            cmd,
+            head: head.to_vec(),
+            body: body.to_vec(),
+        };
+        Box::<[u8]>::from(packet).into_vec()
+    }
+
+    fn assert_packet(packet: &NetPacket, cmd: u16, head: &[u8], body: &[u8]) {
+        assert_eq!(packet.cmd, cmd);
+        assert_eq!(packet.head, head);
+        assert_eq!(packet.body, body);
+    }
+
+    // Hands out one queued chunk per read, to simulate frames arriving piecemeal.
+    struct ChunkedReader(std::collections::VecDeque<Vec<u8>>);
+
+    impl tokio::io::AsyncRead for ChunkedReader {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            if let Some(chunk) = self.0.pop_front() {
+                buf.put_slice(&chunk);
+            }
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    fn chunked(chunks: &[&[u8]]) -> NetPacketReader<ChunkedReader> {
+        NetPacketReader::new(ChunkedReader(
+            chunks.iter().map(|chunk| chunk.to_vec()).collect(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn reader_reassembles_frame_split_across_reads() {
+        let bytes = frame(7, b"head", b"body");
+        let mut reader = chunked(&[&bytes[..3], &bytes[3..13], &bytes[13..]]);
+
+        let packet = reader.read_packet().await.unwrap().unwrap();
+        assert_packet(&packet, 7, b"head", b"body");
+        assert!(reader.read_packet().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn reader_yields_two_frames_from_one_read() {
+        let bytes = [frame(1, b"", b"first"), frame(2, b"h", b"")].concat();
+        let mut reader = chunked(&[&bytes]);
+
+        let first = reader.read_packet().await.unwrap().unwrap();
+        assert_packet(&first, 1, b"", b"first");
+        let second = reader.read_packet().await.unwrap().unwrap();
+        assert_packet(&second, 2, b"h", b"");
+        assert!(reader.read_packet().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn reader_clean_eof_is_none() {
+        let mut reader = chunked(&[]);
+        assert!(reader.read_packet().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn reader_eof_mid_frame_is_too_short() {
+        let bytes = frame(7, b"head", b"body");
+        let mut reader = chunked(&[&bytes[..bytes.len() - 1]]);
+
+        let err = reader.read_packet().await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert!(matches!(
+            err.get_ref().and_then(|e| e.downcast_ref::<PacketError>()),
+            Some(PacketError::TooShort)
+        ));
+    }
+
+    #[tokio::test]
+    async fn reader_rejects_oversized_declared_length() {
+        // Only the header of a frame claiming a 4 GiB body ever arrives.
+        let mut header = frame(7, b"", b"");
+        BE::write_u32(&mut header[BS_START..BS_END], u32::MAX);
+        let mut reader = NetPacketReader::with_max_frame_len(
+            ChunkedReader([header[..BS_END].to_vec()].into()),
+            1024,
+        );
+
+        let err = reader.read_packet().await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(reader.buffer.len() <= BS_END);
+    }
+
+    #[cfg(feature = "codec")]
+    #[test]
+    fn codec_waits_for_partial_frame() {
+        let bytes = frame(7, b"head", b"body");
+        let mut codec = NetPacketCodec::new();
+        let mut src = BytesMut::from(&bytes[..bytes.len() - 1]);
+
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        assert_eq!(src.len(), bytes.len() - 1);
+
+        src.put_slice(&bytes[bytes.len() - 1..]);
+        let packet = codec.decode(&mut src).unwrap().unwrap();
+        assert_packet(&packet, 7, b"head", b"body");
+        assert!(src.is_empty());
+    }
+
+    #[cfg(feature = "codec")]
+    #[test]
+    fn codec_round_trips_packets() {
+        let mut codec = NetPacketCodec::default();
+        let mut buffer = BytesMut::new();
+        for (cmd, head, body) in [(1, &b""[..], &b"first"[..]), (2, b"h", b"")] {
+            let packet = NetPacket {
+                cmd,
+                head: head.to_vec(),
+                body: body.to_vec(),
+            };
+            codec.encode(packet, &mut buffer).unwrap();
+        }
+
+        assert_packet(
+            &codec.decode(&mut buffer).unwrap().unwrap(),
+            1,
+            b"",
+            b"first",
+        );
+        assert_packet(&codec.decode(&mut buffer).unwrap().unwrap(), 2, b"h", b"");
+        assert!(codec.decode(&mut buffer).unwrap().is_none());
+    }
+
+    #[cfg(feature = "codec")]
+    #[test]
+    fn codec_rejects_frame_over_configured_max() {
+        let bytes = frame(7, b"head", b"body");
+        let mut codec = NetPacketCodec::with_max_frame_len(bytes.len() - 1);
+        let mut src = BytesMut::from(&bytes[..]);
+
+        let err = codec.decode(&mut src).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_packet(
+            &NetPacketCodec::with_max_frame_len(bytes.len())
+                .decode(&mut src)
+                .unwrap()
+                .unwrap(),
+            7,
+            b"head",
+            b"body",
+        );
+    }
+}
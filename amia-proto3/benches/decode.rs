@@ -0,0 +1,154 @@
+use amia_proto3::prelude::*;
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+use std::io::{BufReader, Cursor};
+
+fn bench_varints(c: &mut Criterion) {
+    let mut group = c.benchmark_group("varints");
+    for max in [100u64, 1 << 20, u64::MAX] {
+        let mut data = Vec::new();
+        for i in 0..10_000u64 {
+            encode_varint(i.wrapping_mul(0x9E3779B97F4A7C15) % max, &mut data).unwrap();
+        }
+        group.throughput(Throughput::Bytes(data.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("cursor", max), &data, |b, data| {
+            b.iter(|| {
+                let mut cursor = Cursor::new(black_box(data.as_slice()));
+                let mut sum = 0u64;
+                while cursor.position() < data.len() as u64 {
+                    sum = sum.wrapping_add(decode_varint(&mut cursor).unwrap());
+                }
+                sum
+            })
+        });
+
+        group.bench_with_input(
+            BenchmarkId::new("bufread_generic", max),
+            &data,
+            |b, data| {
+                b.iter(|| {
+                    let mut reader = BufReader::new(black_box(data.as_slice()));
+                    let mut sum = 0u64;
+                    for _ in 0..10_000 {
+                        sum = sum.wrapping_add(decode_varint(&mut reader).unwrap());
+                    }
+                    sum
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("bufread_buffered", max),
+            &data,
+            |b, data| {
+                b.iter(|| {
+                    let mut reader = BufReader::new(black_box(data.as_slice()));
+                    let mut sum = 0u64;
+                    for _ in 0..10_000 {
+                        sum = sum.wrapping_add(decode_varint_buffered(&mut reader).unwrap());
+                    }
+                    sum
+                })
+            },
+        );
+
+        group.bench_with_input(BenchmarkId::new("slice", max), &data, |b, data| {
+            b.iter(|| {
+                let mut rest = black_box(data.as_slice());
+                let mut sum = 0u64;
+                while !rest.is_empty() {
+                    let (value, next) = decode_varint_slice(rest).unwrap();
+                    sum = sum.wrapping_add(value);
+                    rest = next;
+                }
+                sum
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_fields(c: &mut Criterion) {
+    let mut data = Vec::new();
+    for i in 0..2_000u32 {
+        encode_uint32(1, i, &mut data).unwrap();
+        encode_string(2, "routing-key", &mut data).unwrap();
+        encode_fixed64(3, i as u64, &mut data).unwrap();
+    }
+
+    let mut group = c.benchmark_group("fields");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+
+    group.bench_function("cursor", |b| {
+        b.iter(|| {
+            let mut cursor = Cursor::new(black_box(data.as_slice()));
+            let mut total = 0usize;
+            while let Some(tag) = Tag::decode(&mut cursor).unwrap() {
+                total += match tag.wire_type {
+                    WireType::Varint => decode_varint(&mut cursor).unwrap() as usize,
+                    WireType::Fixed64 => decode_fixed64(&mut cursor).unwrap() as usize,
+                    _ => decode_bytes(&mut cursor).unwrap().len(),
+                };
+            }
+            total
+        })
+    });
+
+    group.bench_function("slice", |b| {
+        b.iter(|| {
+            let mut rest = black_box(data.as_slice());
+            let mut total = 0usize;
+            loop {
+                let (tag, next) = decode_tag_slice(rest).unwrap();
+                let Some(tag) = tag else { break };
+                rest = match tag.wire_type {
+                    WireType::Varint => {
+                        let (value, next) = decode_varint_slice(next).unwrap();
+                        total += value as usize;
+                        next
+                    }
+                    WireType::Fixed64 => {
+                        let (value, next) = decode_fixed64_slice(next).unwrap();
+                        total += value as usize;
+                        next
+                    }
+                    _ => {
+                        let (bytes, next) = decode_bytes_slice(next).unwrap();
+                        total += bytes.len();
+                        next
+                    }
+                };
+            }
+            total
+        })
+    });
+    group.finish();
+}
+
+fn bench_packed(c: &mut Criterion) {
+    let values: Vec<u64> = (0..10_000u64)
+        .map(|i| i.wrapping_mul(0x9E3779B97F4A7C15) % (1 << 20))
+        .collect();
+    let mut data = Vec::new();
+    encode_packed(1, &values, &mut data, |value, buffer| {
+        encode_varint(*value, buffer)
+    })
+    .unwrap();
+    let payload = &data[1..];
+
+    let mut group = c.benchmark_group("packed_varints");
+    group.throughput(Throughput::Bytes(payload.len() as u64));
+
+    group.bench_function("cursor", |b| {
+        b.iter(|| decode_packed(&mut black_box(payload), decode_uint64).unwrap())
+    });
+
+    group.bench_function("slice", |b| {
+        b.iter(|| decode_packed_varint(&mut black_box(payload), Ok).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_varints, bench_fields, bench_packed);
+criterion_main!(benches);
@@ -0,0 +1,82 @@
+#![no_main]
+
+use amia_proto3::prelude::*;
+use libfuzzer_sys::fuzz_target;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+#[derive(Debug, Default)]
+struct Inner {
+    id: u64,
+    name: String,
+}
+
+impl Protobuf for Inner {
+    fn encode_to_writer<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        encode_uint64(1, self.id, writer)?;
+        encode_string(2, &self.name, writer)
+    }
+
+    fn decode_from_reader<R: Read>(reader: &mut R) -> DecodeResult<Self> {
+        let mut message = Self::default();
+        while let Some(tag) = Tag::decode(reader)? {
+            match (tag.field_number, tag.wire_type) {
+                (1, WireType::Varint) => message.id = decode_uint64(reader)?,
+                (2, WireType::LengthDelimited) => message.name = decode_string(reader)?,
+                (_, wire_type) => {
+                    read_field_value(wire_type, reader)?;
+                }
+            }
+        }
+        Ok(message)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Outer {
+    values: Vec<i32>,
+    scores: Vec<f32>,
+    tags: HashMap<String, i64>,
+    inner: Option<Inner>,
+    data: Vec<u8>,
+}
+
+impl Protobuf for Outer {
+    fn encode_to_writer<W: Write>(&self, _writer: &mut W) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn decode_from_reader<R: Read>(reader: &mut R) -> DecodeResult<Self> {
+        let mut message = Self::default();
+        while let Some(tag) = Tag::decode(reader)? {
+            match (tag.field_number, tag.wire_type) {
+                (1, WireType::LengthDelimited) => {
+                    decode_packed_into(reader, &mut message.values, decode_int32)?
+                }
+                (2, WireType::LengthDelimited) => {
+                    decode_packed_into(reader, &mut message.scores, decode_float)?
+                }
+                (3, WireType::LengthDelimited) => {
+                    let mut entry = Vec::new();
+                    encode_bytes(3, &decode_bytes(reader)?, &mut entry).unwrap();
+                    message.tags.extend(decode_map(
+                        &mut entry.as_slice(),
+                        |c| decode_string(c),
+                        |c| decode_sint64(c),
+                    )?);
+                }
+                (4, WireType::LengthDelimited) => message.inner = Some(decode_message(reader)?),
+                (5, WireType::LengthDelimited) => message.data = decode_bytes(reader)?,
+                (_, wire_type) => {
+                    read_field_value(wire_type, reader)?;
+                }
+            }
+        }
+        Ok(message)
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Outer::decode_from_slice(data);
+    let _ = decode_varint(&mut &data[..]);
+});
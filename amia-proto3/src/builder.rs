@@ -1,11 +1,20 @@
-use crate::{Protobuf, WireType, encoder};
+use crate::{EncodeOptions, IntoFieldNumber, Protobuf, WireType, decoder, encoder};
 use std::collections::{HashMap, HashSet};
-use std::io;
+use std::io::{self, Cursor};
 
-#[derive(Default)]
+#[derive(Clone)]
+struct Segment {
+    field_number: u32,
+    start: usize,
+    is_map: bool,
+}
+
+#[derive(Clone, Default)]
 pub struct ProtobufBuilder {
     buffer: Vec<u8>,
     field_numbers: HashSet<u32>,
+    segments: Vec<Segment>,
+    options: EncodeOptions,
 }
 
 impl ProtobufBuilder {
@@ -13,115 +22,294 @@ impl ProtobufBuilder {
         Self::default()
     }
 
-    fn check_field(&mut self, field_number: u32) {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buffer: Vec::with_capacity(capacity),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_options(options: EncodeOptions) -> Self {
+        Self {
+            options,
+            ..Self::default()
+        }
+    }
+
+    pub fn reserve(&mut self, additional: usize) -> &mut Self {
+        self.buffer.reserve(additional);
+        self
+    }
+
+    fn check_field(&mut self, field_number: impl IntoFieldNumber) {
+        let field_number = field_number.into_field_number();
         if !self.field_numbers.insert(field_number) {
             panic!(
                 "field number {} is already assigned to a field",
                 field_number
             );
         }
+        self.segments.push(Segment {
+            field_number,
+            start: self.buffer.len(),
+            is_map: false,
+        });
     }
 
-    pub fn add_uint32(&mut self, field_number: u32, value: u32) -> &mut Self {
+    fn check_map_field(&mut self, field_number: impl IntoFieldNumber) {
         self.check_field(field_number);
-        encoder::encode_uint32(field_number, value, &mut self.buffer).unwrap();
-        self
+        if let Some(segment) = self.segments.last_mut() {
+            segment.is_map = true;
+        }
     }
 
-    pub fn add_int32(&mut self, field_number: u32, value: i32) -> &mut Self {
+    // Picks the default-skipping encoder or its always-written `encode_oneof_*` twin.
+    fn add_scalar<T>(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        value: T,
+        skipping: fn(u32, T, &mut Vec<u8>) -> io::Result<()>,
+        explicit: fn(u32, T, &mut Vec<u8>) -> io::Result<()>,
+    ) -> &mut Self {
+        let field_number = field_number.into_field_number();
         self.check_field(field_number);
-        encoder::encode_int32(field_number, value, &mut self.buffer).unwrap();
+        let encode = if self.options.skip_defaults {
+            skipping
+        } else {
+            explicit
+        };
+        encode(field_number, value, &mut self.buffer).unwrap();
         self
     }
 
-    pub fn add_int64(&mut self, field_number: u32, value: i64) -> &mut Self {
-        self.check_field(field_number);
-        encoder::encode_int64(field_number, value, &mut self.buffer).unwrap();
-        self
+    // Embedded bytes are copied exactly once, into space reserved for the whole field.
+    fn write_embedded(&mut self, field_number: impl IntoFieldNumber, inner: &[u8]) {
+        self.buffer.reserve(
+            encoder::size_of_tag(field_number)
+                + encoder::size_of_varint(inner.len() as u64)
+                + inner.len(),
+        );
+        encoder::encode_tag(field_number, WireType::LengthDelimited, &mut self.buffer).unwrap();
+        encoder::encode_varint(inner.len() as u64, &mut self.buffer).unwrap();
+        self.buffer.extend_from_slice(inner);
     }
 
-    pub fn add_uint64(&mut self, field_number: u32, value: u64) -> &mut Self {
-        self.check_field(field_number);
-        encoder::encode_uint64(field_number, value, &mut self.buffer).unwrap();
-        self
+    // Writes a map entry whose value is an embedded message without staging the entry.
+    fn write_message_entry(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        key: &[u8],
+        value: &[u8],
+    ) {
+        let value_len = encoder::size_of_varint(value.len() as u64) + value.len();
+        let entry_len = key.len() + encoder::size_of_tag(2) + value_len;
+        self.buffer.reserve(
+            encoder::size_of_tag(field_number)
+                + encoder::size_of_varint(entry_len as u64)
+                + entry_len,
+        );
+        encoder::encode_tag(field_number, WireType::LengthDelimited, &mut self.buffer).unwrap();
+        encoder::encode_varint(entry_len as u64, &mut self.buffer).unwrap();
+        self.buffer.extend_from_slice(key);
+        self.write_embedded(2, value);
     }
 
-    pub fn add_sint32(&mut self, field_number: u32, value: i32) -> &mut Self {
+    // Shared by the fixed-width packed adders; `N` is the element width on the wire.
+    fn add_packed_le<T: Copy, const N: usize>(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        values: &[T],
+        to_le_bytes: fn(T) -> [u8; N],
+    ) -> &mut Self {
         self.check_field(field_number);
-        encoder::encode_sint32(field_number, value, &mut self.buffer).unwrap();
+        encoder::encode_packed_sized(
+            field_number,
+            values,
+            N,
+            &mut self.buffer,
+            |value, buffer| {
+                buffer.extend_from_slice(&to_le_bytes(*value));
+                Ok(())
+            },
+        )
+        .unwrap();
         self
     }
 
-    pub fn add_sint64(&mut self, field_number: u32, value: i64) -> &mut Self {
-        self.check_field(field_number);
-        encoder::encode_sint64(field_number, value, &mut self.buffer).unwrap();
-        self
+    pub fn add_uint32(&mut self, field_number: impl IntoFieldNumber, value: u32) -> &mut Self {
+        self.add_scalar(
+            field_number,
+            value,
+            encoder::encode_uint32,
+            encoder::encode_oneof_uint32,
+        )
     }
 
-    pub fn add_bool(&mut self, field_number: u32, value: bool) -> &mut Self {
-        self.check_field(field_number);
-        encoder::encode_bool(field_number, value, &mut self.buffer).unwrap();
-        self
+    pub fn add_int32(&mut self, field_number: impl IntoFieldNumber, value: i32) -> &mut Self {
+        self.add_scalar(
+            field_number,
+            value,
+            encoder::encode_int32,
+            encoder::encode_oneof_int32,
+        )
     }
 
-    pub fn add_string(&mut self, field_number: u32, value: &str) -> &mut Self {
-        self.check_field(field_number);
-        encoder::encode_string(field_number, value, &mut self.buffer).unwrap();
-        self
+    pub fn add_int64(&mut self, field_number: impl IntoFieldNumber, value: i64) -> &mut Self {
+        self.add_scalar(
+            field_number,
+            value,
+            encoder::encode_int64,
+            encoder::encode_oneof_int64,
+        )
     }
 
-    pub fn add_bytes(&mut self, field_number: u32, value: &[u8]) -> &mut Self {
-        self.check_field(field_number);
-        encoder::encode_bytes(field_number, value, &mut self.buffer).unwrap();
-        self
+    pub fn add_uint64(&mut self, field_number: impl IntoFieldNumber, value: u64) -> &mut Self {
+        self.add_scalar(
+            field_number,
+            value,
+            encoder::encode_uint64,
+            encoder::encode_oneof_uint64,
+        )
     }
 
-    pub fn add_float(&mut self, field_number: u32, value: f32) -> &mut Self {
-        self.check_field(field_number);
-        encoder::encode_float(field_number, value, &mut self.buffer).unwrap();
-        self
+    pub fn add_sint32(&mut self, field_number: impl IntoFieldNumber, value: i32) -> &mut Self {
+        self.add_scalar(
+            field_number,
+            value,
+            encoder::encode_sint32,
+            encoder::encode_oneof_sint32,
+        )
     }
 
-    pub fn add_double(&mut self, field_number: u32, value: f64) -> &mut Self {
-        self.check_field(field_number);
-        encoder::encode_double(field_number, value, &mut self.buffer).unwrap();
-        self
+    pub fn add_sint64(&mut self, field_number: impl IntoFieldNumber, value: i64) -> &mut Self {
+        self.add_scalar(
+            field_number,
+            value,
+            encoder::encode_sint64,
+            encoder::encode_oneof_sint64,
+        )
     }
 
-    pub fn add_fixed32(&mut self, field_number: u32, value: u32) -> &mut Self {
-        self.check_field(field_number);
-        encoder::encode_fixed32(field_number, value, &mut self.buffer).unwrap();
-        self
+    pub fn add_bool(&mut self, field_number: impl IntoFieldNumber, value: bool) -> &mut Self {
+        self.add_scalar(
+            field_number,
+            value,
+            encoder::encode_bool,
+            encoder::encode_oneof_bool,
+        )
     }
 
-    pub fn add_fixed64(&mut self, field_number: u32, value: u64) -> &mut Self {
-        self.check_field(field_number);
-        encoder::encode_fixed64(field_number, value, &mut self.buffer).unwrap();
-        self
+    pub fn add_string(&mut self, field_number: impl IntoFieldNumber, value: &str) -> &mut Self {
+        self.add_scalar(
+            field_number,
+            value,
+            encoder::encode_string,
+            encoder::encode_oneof_string,
+        )
     }
 
-    pub fn add_sfixed32(&mut self, field_number: u32, value: i32) -> &mut Self {
-        self.check_field(field_number);
-        encoder::encode_sfixed32(field_number, value, &mut self.buffer).unwrap();
-        self
+    pub fn add_bytes(&mut self, field_number: impl IntoFieldNumber, value: &[u8]) -> &mut Self {
+        self.add_scalar(
+            field_number,
+            value,
+            encoder::encode_bytes,
+            encoder::encode_oneof_bytes,
+        )
     }
 
-    pub fn add_sfixed64(&mut self, field_number: u32, value: i64) -> &mut Self {
+    pub fn add_bytes_owned(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        mut value: Vec<u8>,
+    ) -> &mut Self {
         self.check_field(field_number);
-        encoder::encode_sfixed64(field_number, value, &mut self.buffer).unwrap();
+        if value.is_empty() && self.options.skip_defaults {
+            return self;
+        }
+
+        encoder::encode_tag(field_number, WireType::LengthDelimited, &mut self.buffer).unwrap();
+        encoder::encode_varint(value.len() as u64, &mut self.buffer).unwrap();
+        self.buffer.append(&mut value);
         self
     }
 
-    pub fn add_enum<E: Into<i32>>(&mut self, field_number: u32, value: E) -> &mut Self {
-        self.check_field(field_number);
-        encoder::encode_enum(field_number, value, &mut self.buffer).unwrap();
-        self
+    pub fn add_float(&mut self, field_number: impl IntoFieldNumber, value: f32) -> &mut Self {
+        self.add_scalar(
+            field_number,
+            value,
+            encoder::encode_float,
+            encoder::encode_oneof_float,
+        )
+    }
+
+    pub fn add_double(&mut self, field_number: impl IntoFieldNumber, value: f64) -> &mut Self {
+        self.add_scalar(
+            field_number,
+            value,
+            encoder::encode_double,
+            encoder::encode_oneof_double,
+        )
     }
 
-    pub fn add_repeated<T, F>(&mut self, field_number: u32, values: &[T], encoder: F) -> &mut Self
+    pub fn add_fixed32(&mut self, field_number: impl IntoFieldNumber, value: u32) -> &mut Self {
+        self.add_scalar(
+            field_number,
+            value,
+            encoder::encode_fixed32,
+            encoder::encode_oneof_fixed32,
+        )
+    }
+
+    pub fn add_fixed64(&mut self, field_number: impl IntoFieldNumber, value: u64) -> &mut Self {
+        self.add_scalar(
+            field_number,
+            value,
+            encoder::encode_fixed64,
+            encoder::encode_oneof_fixed64,
+        )
+    }
+
+    pub fn add_sfixed32(&mut self, field_number: impl IntoFieldNumber, value: i32) -> &mut Self {
+        self.add_scalar(
+            field_number,
+            value,
+            encoder::encode_sfixed32,
+            encoder::encode_oneof_sfixed32,
+        )
+    }
+
+    pub fn add_sfixed64(&mut self, field_number: impl IntoFieldNumber, value: i64) -> &mut Self {
+        self.add_scalar(
+            field_number,
+            value,
+            encoder::encode_sfixed64,
+            encoder::encode_oneof_sfixed64,
+        )
+    }
+
+    pub fn add_enum<E: Into<i32>>(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        value: E,
+    ) -> &mut Self {
+        self.add_scalar(
+            field_number,
+            value,
+            encoder::encode_enum,
+            encoder::encode_oneof_enum,
+        )
+    }
+
+    pub fn add_repeated<T, F>(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        values: &[T],
+        encoder: F,
+    ) -> &mut Self
     where
         F: Fn(u32, &T, &mut Vec<u8>) -> io::Result<()>,
     {
+        let field_number = field_number.into_field_number();
         self.check_field(field_number);
         for value in values {
             encoder(field_number, value, &mut self.buffer).unwrap();
@@ -131,7 +319,7 @@ impl ProtobufBuilder {
 
     pub fn add_packed<T>(
         &mut self,
-        field_number: u32,
+        field_number: impl IntoFieldNumber,
         values: &[T],
         value_writer: impl Fn(&T, &mut Vec<u8>) -> io::Result<()>,
     ) -> &mut Self {
@@ -140,9 +328,103 @@ impl ProtobufBuilder {
         self
     }
 
+    pub fn add_packed_enum<E: Into<i32> + Copy>(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        values: &[E],
+    ) -> &mut Self {
+        self.check_field(field_number);
+        encoder::encode_packed_enum(field_number, values, &mut self.buffer).unwrap();
+        self
+    }
+
+    pub fn add_packed_sint32(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        values: &[i32],
+    ) -> &mut Self {
+        self.add_packed(field_number, values, |value, buffer| {
+            encoder::encode_zigzag(*value as i64, buffer)
+        })
+    }
+
+    pub fn add_packed_sint64(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        values: &[i64],
+    ) -> &mut Self {
+        self.add_packed(field_number, values, |value, buffer| {
+            encoder::encode_zigzag(*value, buffer)
+        })
+    }
+
+    pub fn add_packed_fixed32(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        values: &[u32],
+    ) -> &mut Self {
+        self.add_packed_le(field_number, values, u32::to_le_bytes)
+    }
+
+    pub fn add_packed_fixed64(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        values: &[u64],
+    ) -> &mut Self {
+        self.add_packed_le(field_number, values, u64::to_le_bytes)
+    }
+
+    pub fn add_packed_sfixed32(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        values: &[i32],
+    ) -> &mut Self {
+        self.add_packed_le(field_number, values, i32::to_le_bytes)
+    }
+
+    pub fn add_packed_sfixed64(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        values: &[i64],
+    ) -> &mut Self {
+        self.add_packed_le(field_number, values, i64::to_le_bytes)
+    }
+
+    pub fn add_packed_float(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        values: &[f32],
+    ) -> &mut Self {
+        self.add_packed_le(field_number, values, f32::to_le_bytes)
+    }
+
+    pub fn add_packed_double(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        values: &[f64],
+    ) -> &mut Self {
+        self.add_packed_le(field_number, values, f64::to_le_bytes)
+    }
+
+    pub fn add_raw_packed(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        packed_bytes: &[u8],
+    ) -> &mut Self {
+        self.check_field(field_number);
+        if packed_bytes.is_empty() {
+            return self;
+        }
+
+        encoder::encode_tag(field_number, WireType::LengthDelimited, &mut self.buffer).unwrap();
+        encoder::encode_varint(packed_bytes.len() as u64, &mut self.buffer).unwrap();
+        self.buffer.extend_from_slice(packed_bytes);
+        self
+    }
+
     pub fn add_map<K, V, IK, IV>(
         &mut self,
-        field_number: u32,
+        field_number: impl IntoFieldNumber,
         map: HashMap<K, V>,
         key_encoder: IK,
         value_encoder: IV,
@@ -151,7 +433,7 @@ impl ProtobufBuilder {
         IK: FnMut(u32, &K, &mut Vec<u8>) -> io::Result<()>,
         IV: FnMut(u32, &V, &mut Vec<u8>) -> io::Result<()>,
     {
-        self.check_field(field_number);
+        self.check_map_field(field_number);
         encoder::encode_map(
             field_number,
             map,
@@ -163,28 +445,57 @@ impl ProtobufBuilder {
         self
     }
 
-    pub fn add_message(&mut self, field_number: u32, mut message: ProtobufBuilder) -> &mut Self {
-        self.check_field(field_number);
+    pub fn add_map_ordered<K, V, IK, IV>(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        entries: impl IntoIterator<Item = (K, V)>,
+        key_encoder: IK,
+        value_encoder: IV,
+    ) -> &mut Self
+    where
+        IK: FnMut(u32, &K, &mut Vec<u8>) -> io::Result<()>,
+        IV: FnMut(u32, &V, &mut Vec<u8>) -> io::Result<()>,
+    {
+        self.check_map_field(field_number);
+        encoder::encode_map_ordered(
+            field_number,
+            entries,
+            &mut self.buffer,
+            key_encoder,
+            value_encoder,
+        )
+        .unwrap();
+        self
+    }
 
-        let inner_bytes = message.build();
-        encoder::encode_tag(field_number, WireType::LengthDelimited, &mut self.buffer).unwrap();
-        encoder::encode_varint(inner_bytes.len() as u64, &mut self.buffer).unwrap();
-        self.buffer.extend_from_slice(&inner_bytes);
+    pub fn add_message(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        message: ProtobufBuilder,
+    ) -> &mut Self {
+        self.check_field(field_number);
+        self.write_embedded(field_number, message.as_bytes());
+        self
+    }
 
+    pub fn add_message_ref(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        message: &ProtobufBuilder,
+    ) -> &mut Self {
+        self.check_field(field_number);
+        self.write_embedded(field_number, message.as_bytes());
         self
     }
 
     pub fn add_repeated_message(
         &mut self,
-        field_number: u32,
+        field_number: impl IntoFieldNumber,
         messages: Vec<ProtobufBuilder>,
     ) -> &mut Self {
         self.check_field(field_number);
-        for mut message in messages {
-            let inner_bytes = message.build();
-            encoder::encode_tag(field_number, WireType::LengthDelimited, &mut self.buffer).unwrap();
-            encoder::encode_varint(inner_bytes.len() as u64, &mut self.buffer).unwrap();
-            self.buffer.extend_from_slice(&inner_bytes);
+        for message in &messages {
+            self.write_embedded(field_number, message.as_bytes());
         }
 
         self
@@ -192,24 +503,16 @@ impl ProtobufBuilder {
 
     pub fn add_map_message_value<K>(
         &mut self,
-        field_number: u32,
+        field_number: impl IntoFieldNumber,
         map: HashMap<K, ProtobufBuilder>,
         mut key_encoder: impl FnMut(u32, &K, &mut Vec<u8>) -> io::Result<()>,
     ) -> &mut Self {
-        self.check_field(field_number);
-        for (key, mut value) in map {
-            let mut entry_buf = Vec::new();
-
-            key_encoder(1, &key, &mut entry_buf).unwrap();
-
-            let value_bytes = value.build();
-            encoder::encode_tag(2, WireType::LengthDelimited, &mut entry_buf).unwrap();
-            encoder::encode_varint(value_bytes.len() as u64, &mut entry_buf).unwrap();
-            entry_buf.extend_from_slice(&value_bytes);
-
-            encoder::encode_tag(field_number, WireType::LengthDelimited, &mut self.buffer).unwrap();
-            encoder::encode_varint(entry_buf.len() as u64, &mut self.buffer).unwrap();
-            self.buffer.extend_from_slice(&entry_buf);
+        self.check_map_field(field_number);
+        let mut key_buf = Vec::new();
+        for (key, value) in map {
+            key_buf.clear();
+            key_encoder(1, &key, &mut key_buf).unwrap();
+            self.write_message_entry(field_number, &key_buf, value.as_bytes());
         }
 
         self
@@ -217,63 +520,189 @@ impl ProtobufBuilder {
 
     pub fn add_message_with_trait<P: Protobuf>(
         &mut self,
-        field_number: u32,
+        field_number: impl IntoFieldNumber,
         message: P,
     ) -> &mut Self {
         self.check_field(field_number);
-
-        let inner_bytes = message.encode_to_vec();
-        encoder::encode_tag(field_number, WireType::LengthDelimited, &mut self.buffer).unwrap();
-        encoder::encode_varint(inner_bytes.len() as u64, &mut self.buffer).unwrap();
-        self.buffer.extend_from_slice(&inner_bytes);
-
+        self.write_embedded(field_number, &message.encode_to_vec());
         self
     }
 
     pub fn add_repeated_message_with_trait<P: Protobuf>(
         &mut self,
-        field_number: u32,
+        field_number: impl IntoFieldNumber,
         messages: Vec<P>,
     ) -> &mut Self {
         self.check_field(field_number);
         for message in messages {
-            let inner_bytes = message.encode_to_vec();
-            encoder::encode_tag(field_number, WireType::LengthDelimited, &mut self.buffer).unwrap();
-            encoder::encode_varint(inner_bytes.len() as u64, &mut self.buffer).unwrap();
-            self.buffer.extend_from_slice(&inner_bytes);
+            self.write_embedded(field_number, &message.encode_to_vec());
         }
 
         self
     }
 
+    pub fn add_repeated_message_with_scratch<P: Protobuf>(
+        &mut self,
+        field_number: impl IntoFieldNumber,
+        messages: &[P],
+    ) -> &mut Self {
+        self.check_field(field_number);
+        encoder::encode_repeated_message(field_number, messages, &mut self.buffer).unwrap();
+        self
+    }
+
     pub fn add_map_message_value_with_trait<K, P: Protobuf>(
         &mut self,
-        field_number: u32,
+        field_number: impl IntoFieldNumber,
         map: HashMap<K, P>,
         mut key_encoder: impl FnMut(u32, &K, &mut Vec<u8>) -> io::Result<()>,
     ) -> &mut Self {
-        self.check_field(field_number);
+        self.check_map_field(field_number);
+        let mut key_buf = Vec::new();
         for (key, value) in map {
-            let mut entry_buf = Vec::new();
-
-            key_encoder(1, &key, &mut entry_buf).unwrap();
-
-            let value_bytes = value.encode_to_vec();
-            encoder::encode_tag(2, WireType::LengthDelimited, &mut entry_buf).unwrap();
-            encoder::encode_varint(value_bytes.len() as u64, &mut entry_buf).unwrap();
-            entry_buf.extend_from_slice(&value_bytes);
-
-            encoder::encode_tag(field_number, WireType::LengthDelimited, &mut self.buffer).unwrap();
-            encoder::encode_varint(entry_buf.len() as u64, &mut self.buffer).unwrap();
-            self.buffer.extend_from_slice(&entry_buf);
+            key_buf.clear();
+            key_encoder(1, &key, &mut key_buf).unwrap();
+            self.write_message_entry(field_number, &key_buf, &value.encode_to_vec());
         }
 
         self
     }
 
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
     pub fn build(&mut self) -> Vec<u8> {
         let result = std::mem::take(&mut self.buffer);
         std::mem::drop(std::mem::take(&mut self.field_numbers));
+        std::mem::drop(std::mem::take(&mut self.segments));
+        result
+    }
+
+    #[cfg(feature = "bytes")]
+    pub fn build_into_bytes(&mut self, dst: &mut bytes::BytesMut) {
+        let buffer = self.build();
+        if dst.is_empty() {
+            // A freshly converted Vec is uniquely owned, so this takes its allocation as-is.
+            *dst = bytes::Bytes::from(buffer)
+                .try_into_mut()
+                .unwrap_or_else(|bytes| bytes::BytesMut::from(&bytes[..]));
+        } else {
+            dst.extend_from_slice(&buffer);
+        }
+    }
+
+    /// Builds with fields in ascending field-number order and map entries sorted by
+    /// their encoded bytes, so equal content always produces equal output.
+    ///
+    /// This is meant for signing and deduplication; it is not the protobuf
+    /// specification's canonical form. Nested builders are embedded as they were built.
+    pub fn build_canonical(&mut self) -> Vec<u8> {
+        let mut segments = std::mem::take(&mut self.segments);
+        let buffer = self.build();
+
+        let ends = segments
+            .iter()
+            .skip(1)
+            .map(|segment| segment.start)
+            .chain(std::iter::once(buffer.len()))
+            .collect::<Vec<_>>();
+        let mut ranges = segments
+            .drain(..)
+            .zip(ends)
+            .map(|(segment, end)| (segment.field_number, segment.is_map, segment.start..end))
+            .collect::<Vec<_>>();
+        ranges.sort_by_key(|(field_number, _, _)| *field_number);
+
+        let mut result = Vec::with_capacity(buffer.len());
+        for (_, is_map, range) in ranges {
+            let bytes = &buffer[range];
+            if is_map {
+                let mut entries = split_entries(bytes);
+                entries.sort_unstable();
+                entries
+                    .iter()
+                    .for_each(|entry| result.extend_from_slice(entry));
+            } else {
+                result.extend_from_slice(bytes);
+            }
+        }
         result
     }
+
+    pub fn build_and_verify<M: Protobuf>(&mut self) -> Vec<u8> {
+        let result = self.build();
+        #[cfg(debug_assertions)]
+        if let Err(err) = M::decode_from_slice(&result) {
+            panic!("built message failed to decode: {:?}", err);
+        }
+        result
+    }
+}
+
+fn split_entries(bytes: &[u8]) -> Vec<&[u8]> {
+    let mut entries = Vec::new();
+    let mut cursor = Cursor::new(bytes);
+    while (cursor.position() as usize) < bytes.len() {
+        let start = cursor.position() as usize;
+        decoder::decode_varint(&mut cursor).unwrap();
+        let length = decoder::decode_varint(&mut cursor).unwrap() as usize;
+        let end = cursor.position() as usize + length;
+        entries.push(&bytes[start..end]);
+        cursor.set_position(end as u64);
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packed_double_matches_hand_built_bytes() {
+        // repeated double field 5 = [1.0, -2.5], packed.
+        let expected = [
+            0x2A, 0x10, //
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xF0, 0x3F, //
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0xC0,
+        ];
+        let bytes = ProtobufBuilder::new()
+            .add_packed_double(5, &[1.0, -2.5])
+            .build();
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn raw_packed_matches_add_packed() {
+        let values = [1u64, 300, 0, u64::MAX];
+        let mut packed = Vec::new();
+        for value in &values {
+            encoder::encode_varint(*value, &mut packed).unwrap();
+        }
+
+        let raw = ProtobufBuilder::new().add_raw_packed(7, &packed).build();
+        let encoded = ProtobufBuilder::new()
+            .add_packed(7, &values, |value, buffer| {
+                encoder::encode_varint(*value, buffer)
+            })
+            .build();
+        assert_eq!(raw, encoded);
+    }
+
+    #[test]
+    fn packed_enum_sign_extends_negative_discriminants() {
+        let bytes = ProtobufBuilder::new()
+            .add_packed_enum(4, &[-1i32, 2])
+            .build();
+        let expected = [
+            0x22, 0x0B, //
+            0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01, //
+            0x02,
+        ];
+        assert_eq!(bytes, expected);
+        assert_eq!(
+            crate::decoder::decode_packed_enum_field(4, &mut &bytes[..], Some).unwrap(),
+            Some(vec![-1, 2])
+        );
+    }
 }
@@ -1,8 +1,14 @@
-use crate::{DecodeError, DecodeResult, Protobuf, WireType};
+use crate::{DecodeError, DecodeResult, FieldNumber, Protobuf, WireType, encoder};
 use std::collections::HashMap;
-use std::io::{self, Cursor, Read, Seek};
+use std::io::{self, BufRead, Cursor, Read, Seek, Write};
+use std::marker::PhantomData;
 
-#[derive(Debug)]
+const PREALLOC_LIMIT: u64 = 64 * 1024;
+
+// Owned so that generic decoders like `decode_sint32` can be passed as packed item decoders.
+pub type PackedCursor = Cursor<Vec<u8>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Tag {
     pub field_number: u32,
     pub wire_type: WireType,
@@ -16,41 +22,157 @@ impl Tag {
         }
     }
 
+    pub fn encode<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        encoder::encode_tag(self.field_number, self.wire_type, writer)
+    }
+
     pub fn decode<R: Read>(reader: &mut R) -> DecodeResult<Option<Self>> {
-        match decode_varint(reader) {
-            Ok(0) => Ok(None),
-            Ok(tag) => {
-                let field_number = tag >> 3;
-                if field_number == 0 {
-                    return Err(DecodeError::InvalidTag);
-                }
-
-                let wire_type_value = (tag & 0x7) as u8;
-                let wire_type = match wire_type_value {
-                    0 => WireType::Varint,
-                    1 => WireType::Fixed64,
-                    2 => WireType::LengthDelimited,
-                    // 3 => WireType::StartGroup,
-                    // 4 => WireType::EndGroup,
-                    5 => WireType::Fixed32,
-                    _ => return Err(DecodeError::InvalidWireType(wire_type_value as u32)),
-                };
-
-                Ok(Some(Tag {
-                    field_number: field_number.try_into().unwrap(),
-                    wire_type,
-                }))
+        // EOF before the first tag byte ends the message; EOF inside the tag does not.
+        let mut first = [0u8; 1];
+        match reader.read_exact(&mut first) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(DecodeError::IoError(e)),
+        }
+
+        Self::from_raw(decode_varint(&mut (&first[..]).chain(reader.by_ref()))?)
+    }
+
+    fn from_raw(tag: u64) -> DecodeResult<Option<Self>> {
+        if tag == 0 {
+            return Ok(None);
+        }
+
+        let field_number = tag >> 3;
+        if field_number == 0 || field_number > FieldNumber::MAX as u64 {
+            return Err(DecodeError::InvalidTag);
+        }
+
+        let wire_type = WireType::try_from((tag & 0x7) as u8)?;
+
+        Ok(Some(Tag {
+            field_number: field_number as u32,
+            wire_type,
+        }))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WireValue {
+    Varint(u64),
+    Fixed64(u64),
+    LengthDelimited(Vec<u8>),
+    Fixed32(u32),
+}
+
+impl WireValue {
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            WireValue::Varint(value) => Some(*value as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_sint(&self) -> Option<i64> {
+        match self {
+            WireValue::Varint(value) => Some(decode_zigzag(*value)),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            WireValue::Varint(value) => Some(*value != 0),
+            _ => None,
+        }
+    }
+}
+
+#[inline]
+pub fn read_field_value<R: Read>(wire_type: WireType, reader: &mut R) -> DecodeResult<WireValue> {
+    match wire_type {
+        WireType::Varint => Ok(WireValue::Varint(decode_varint(reader)?)),
+        WireType::Fixed64 => Ok(WireValue::Fixed64(decode_fixed64(reader)?)),
+        WireType::LengthDelimited => Ok(WireValue::LengthDelimited(decode_bytes(reader)?)),
+        WireType::Fixed32 => Ok(WireValue::Fixed32(decode_fixed32(reader)?)),
+        _ => Err(DecodeError::InvalidWireType(wire_type as u32)),
+    }
+}
+
+#[inline]
+pub fn skip_field<R: Read>(wire_type: WireType, reader: &mut R) -> DecodeResult<()> {
+    if let Some(width) = wire_type.fixed_width() {
+        let mut buffer = [0u8; 8];
+        reader.read_exact(&mut buffer[..width])?;
+        return Ok(());
+    }
+
+    match wire_type {
+        WireType::Varint => {
+            decode_varint(reader)?;
+        }
+        WireType::LengthDelimited => {
+            let length = decode_varint(reader)?;
+            if io::copy(&mut reader.by_ref().take(length), &mut io::sink())? < length {
+                return Err(DecodeError::UnexpectedEof);
             }
-            Err(e) => match e {
-                DecodeError::UnexpectedEof => Ok(None),
-                _ => Err(e),
-            },
+        }
+        _ => return Err(DecodeError::InvalidWireType(wire_type as u32)),
+    }
+    Ok(())
+}
+
+/// Scans to the first `target_field_number` field, skipping the others without allocating.
+#[inline]
+pub fn find_field<R: Read>(
+    reader: &mut R,
+    target_field_number: u32,
+) -> DecodeResult<Option<(WireType, WireValue)>> {
+    while let Some(tag) = Tag::decode(reader)? {
+        if tag.field_number == target_field_number {
+            return Ok(Some((
+                tag.wire_type,
+                read_field_value(tag.wire_type, reader)?,
+            )));
+        }
+        skip_field(tag.wire_type, reader)?;
+    }
+    Ok(None)
+}
+
+/// Receives each field of a message in wire order, as driven by [`decode_with_visitor`].
+pub trait FieldVisitor {
+    fn visit_field(&mut self, tag: Tag, value: WireValue) -> DecodeResult<()>;
+
+    /// Fields this returns `false` for are skipped without being read into a `WireValue`.
+    fn wants_field(&self, _tag: Tag) -> bool {
+        true
+    }
+}
+
+pub fn decode_with_visitor<R: Read, V: FieldVisitor + ?Sized>(
+    reader: &mut R,
+    visitor: &mut V,
+) -> DecodeResult<()> {
+    while let Some(tag) = Tag::decode(reader)? {
+        if visitor.wants_field(tag) {
+            let value = read_field_value(tag.wire_type, reader)?;
+            visitor.visit_field(tag, value)?;
+        } else {
+            skip_field(tag.wire_type, reader)?;
         }
     }
+    Ok(())
 }
 
 #[inline]
 pub fn decode_varint<R: Read>(reader: &mut R) -> DecodeResult<u64> {
+    decode_varint_with_len(reader).map(|(value, _)| value)
+}
+
+/// Like [`decode_varint`], but also returns how many bytes the varint took.
+#[inline]
+pub fn decode_varint_with_len<R: Read>(reader: &mut R) -> DecodeResult<(u64, usize)> {
     let mut result: u64 = 0;
     let mut shift: u32 = 0;
 
@@ -65,15 +187,42 @@ pub fn decode_varint<R: Read>(reader: &mut R) -> DecodeResult<u64> {
         }
 
         let byte = buf[0];
+
+        // The 10th byte only carries bit 63, so anything above 0x01 overflows.
+        if shift == 63 && byte > 0x01 {
+            return Err(DecodeError::InvalidVarint {
+                consumed: 10,
+                partial_value: result,
+            });
+        }
+
         result |= ((byte & 0x7F) as u64) << shift;
 
         if byte & 0x80 == 0 {
-            return Ok(result);
+            return Ok((result, (shift / 7 + 1) as usize));
         }
 
         shift += 7;
-        if shift > 63 {
-            return Err(DecodeError::InvalidVarint);
+    }
+}
+
+#[inline]
+pub fn decode_varint_buffered<R: BufRead>(reader: &mut R) -> DecodeResult<u64> {
+    let decoded = {
+        let buf = reader.fill_buf()?;
+        decode_varint_slice(buf).map(|(value, rest)| (value, buf.len() - rest.len()))
+    };
+
+    match decoded {
+        Ok((value, consumed)) => {
+            reader.consume(consumed);
+            Ok(value)
+        }
+        // Split across the buffer boundary (or truncated): finish byte by byte.
+        Err(DecodeError::UnexpectedEof) => decode_varint(reader),
+        Err(err) => {
+            reader.consume(10);
+            Err(err)
         }
     }
 }
@@ -83,6 +232,15 @@ pub fn decode_tag<R: Read>(reader: &mut R) -> DecodeResult<Option<Tag>> {
     Tag::decode(reader)
 }
 
+/// Decodes the next tag and rewinds to it, so a dispatch loop can look before it reads.
+#[inline]
+pub fn peek_tag<R: Read + Seek>(reader: &mut R) -> DecodeResult<Option<Tag>> {
+    let start = reader.stream_position()?;
+    let tag = Tag::decode(reader);
+    reader.seek(io::SeekFrom::Start(start))?;
+    tag
+}
+
 #[inline]
 pub fn decode_zigzag(value: u64) -> i64 {
     ((value >> 1) as i64) ^ (-((value & 1) as i64))
@@ -131,8 +289,33 @@ pub fn decode_bool<R: Read>(reader: &mut R) -> DecodeResult<bool> {
 
 #[inline]
 pub fn decode_bytes<R: Read>(reader: &mut R) -> DecodeResult<Vec<u8>> {
-    let length = decode_varint(reader)? as usize;
-    let mut buffer = vec![0u8; length];
+    // The length prefix is untrusted, so never allocate more than has actually been read.
+    let length = decode_varint(reader)?;
+    let mut buffer = Vec::with_capacity(length.min(PREALLOC_LIMIT) as usize);
+    reader.by_ref().take(length).read_to_end(&mut buffer)?;
+    if (buffer.len() as u64) < length {
+        return Err(DecodeError::UnexpectedEof);
+    }
+    Ok(buffer)
+}
+
+#[inline]
+pub fn decode_bytes_checked<R: Read + Seek>(reader: &mut R) -> DecodeResult<Vec<u8>> {
+    let length = decode_varint(reader)?;
+
+    let position = reader.stream_position()?;
+    let end = reader.seek(io::SeekFrom::End(0))?;
+    reader.seek(io::SeekFrom::Start(position))?;
+
+    let remaining = end.saturating_sub(position);
+    if length > remaining {
+        return Err(DecodeError::MalformedInput(format!(
+            "Length {} exceeds remaining {} bytes",
+            length, remaining
+        )));
+    }
+
+    let mut buffer = vec![0u8; length as usize];
     reader.read_exact(&mut buffer)?;
     Ok(buffer)
 }
@@ -144,45 +327,155 @@ pub fn decode_string<R: Read>(reader: &mut R) -> DecodeResult<String> {
 }
 
 #[inline]
-pub fn decode_float<R: Read>(reader: &mut R) -> DecodeResult<f32> {
-    let mut buffer = [0u8; 4];
+pub fn decode_string_lossy<R: Read>(reader: &mut R) -> DecodeResult<String> {
+    let bytes = decode_bytes(reader)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// A short read becomes DecodeError::UnexpectedEof through From<io::Error>.
+#[inline]
+fn read_fixed<const N: usize, R: Read>(reader: &mut R) -> DecodeResult<[u8; N]> {
+    let mut buffer = [0u8; N];
     reader.read_exact(&mut buffer)?;
-    Ok(f32::from_le_bytes(buffer))
+    Ok(buffer)
+}
+
+#[inline]
+pub fn decode_float<R: Read>(reader: &mut R) -> DecodeResult<f32> {
+    Ok(f32::from_le_bytes(read_fixed(reader)?))
 }
 
 #[inline]
 pub fn decode_double<R: Read>(reader: &mut R) -> DecodeResult<f64> {
-    let mut buffer = [0u8; 8];
-    reader.read_exact(&mut buffer)?;
-    Ok(f64::from_le_bytes(buffer))
+    Ok(f64::from_le_bytes(read_fixed(reader)?))
+}
+
+#[inline]
+pub fn decode_float_finite<R: Read>(reader: &mut R) -> DecodeResult<f32> {
+    let value = decode_float(reader)?;
+    if !value.is_finite() {
+        return Err(DecodeError::MalformedInput(format!(
+            "Non-finite float value: {}",
+            value
+        )));
+    }
+    Ok(value)
+}
+
+#[inline]
+pub fn decode_double_finite<R: Read>(reader: &mut R) -> DecodeResult<f64> {
+    let value = decode_double(reader)?;
+    if !value.is_finite() {
+        return Err(DecodeError::MalformedInput(format!(
+            "Non-finite double value: {}",
+            value
+        )));
+    }
+    Ok(value)
 }
 
 #[inline]
 pub fn decode_fixed32<R: Read>(reader: &mut R) -> DecodeResult<u32> {
-    let mut buffer = [0u8; 4];
-    reader.read_exact(&mut buffer)?;
-    Ok(u32::from_le_bytes(buffer))
+    Ok(u32::from_le_bytes(read_fixed(reader)?))
 }
 
 #[inline]
 pub fn decode_fixed64<R: Read>(reader: &mut R) -> DecodeResult<u64> {
-    let mut buffer = [0u8; 8];
-    reader.read_exact(&mut buffer)?;
-    Ok(u64::from_le_bytes(buffer))
+    Ok(u64::from_le_bytes(read_fixed(reader)?))
+}
+
+// Non-standard big-endian fixed fields, for servers that do not follow the protobuf spec.
+#[inline]
+pub fn decode_fixed32_be<R: Read>(reader: &mut R) -> DecodeResult<u32> {
+    Ok(u32::from_be_bytes(read_fixed(reader)?))
+}
+
+#[inline]
+pub fn decode_fixed64_be<R: Read>(reader: &mut R) -> DecodeResult<u64> {
+    Ok(u64::from_be_bytes(read_fixed(reader)?))
 }
 
 #[inline]
 pub fn decode_sfixed32<R: Read>(reader: &mut R) -> DecodeResult<i32> {
-    let mut buffer = [0u8; 4];
-    reader.read_exact(&mut buffer)?;
-    Ok(i32::from_le_bytes(buffer))
+    Ok(i32::from_le_bytes(read_fixed(reader)?))
 }
 
 #[inline]
 pub fn decode_sfixed64<R: Read>(reader: &mut R) -> DecodeResult<i64> {
-    let mut buffer = [0u8; 8];
-    reader.read_exact(&mut buffer)?;
-    Ok(i64::from_le_bytes(buffer))
+    Ok(i64::from_le_bytes(read_fixed(reader)?))
+}
+
+// Slice-based primitives for in-memory input: no io::Read dispatch, each returns the rest.
+#[inline]
+pub fn decode_varint_slice(buf: &[u8]) -> DecodeResult<(u64, &[u8])> {
+    if let Some((&byte, rest)) = buf.split_first()
+        && byte < 0x80
+    {
+        return Ok((byte as u64, rest));
+    }
+
+    let mut result: u64 = 0;
+    let mut shift: u32 = 0;
+
+    for (i, &byte) in buf.iter().take(10).enumerate() {
+        result |= ((byte & 0x7F) as u64) << shift;
+
+        if byte < 0x80 {
+            if i == 9 && byte > 0x01 {
+                break;
+            }
+            return Ok((result, &buf[i + 1..]));
+        }
+
+        shift += 7;
+    }
+
+    if buf.len() < 10 {
+        return Err(DecodeError::UnexpectedEof);
+    }
+
+    // Same as decode_varint: the 10th byte only carries bit 63.
+    Err(DecodeError::InvalidVarint {
+        consumed: 10,
+        partial_value: result & (u64::MAX >> 1),
+    })
+}
+
+/// Like [`decode_varint_slice`], but returns how many bytes the varint took.
+#[inline]
+pub fn decode_varint_from_slice(buf: &[u8]) -> DecodeResult<(u64, usize)> {
+    let (value, rest) = decode_varint_slice(buf)?;
+    Ok((value, buf.len() - rest.len()))
+}
+
+#[inline]
+pub fn decode_tag_slice(buf: &[u8]) -> DecodeResult<(Option<Tag>, &[u8])> {
+    if buf.is_empty() {
+        return Ok((None, buf));
+    }
+    let (tag, rest) = decode_varint_slice(buf)?;
+    Ok((Tag::from_raw(tag)?, rest))
+}
+
+#[inline]
+pub fn decode_fixed32_slice(buf: &[u8]) -> DecodeResult<(u32, &[u8])> {
+    let (bytes, rest) = buf.split_first_chunk().ok_or(DecodeError::UnexpectedEof)?;
+    Ok((u32::from_le_bytes(*bytes), rest))
+}
+
+#[inline]
+pub fn decode_fixed64_slice(buf: &[u8]) -> DecodeResult<(u64, &[u8])> {
+    let (bytes, rest) = buf.split_first_chunk().ok_or(DecodeError::UnexpectedEof)?;
+    Ok((u64::from_le_bytes(*bytes), rest))
+}
+
+#[inline]
+pub fn decode_bytes_slice(buf: &[u8]) -> DecodeResult<(&[u8], &[u8])> {
+    let (length, rest) = decode_varint_slice(buf)?;
+    if length > rest.len() as u64 {
+        return Err(DecodeError::UnexpectedEof);
+    }
+    Ok(rest.split_at(length as usize))
 }
 
 #[inline]
@@ -539,12 +832,27 @@ where
     M: Protobuf,
     R: Read,
 {
-    let length = decode_varint(reader)? as usize;
-
-    let mut buffer = vec![0u8; length];
-    reader.read_exact(&mut buffer)?;
+    let length = decode_varint(reader)?;
+
+    // Erased so that recursive message types don't instantiate `Take<&mut Take<..>>` without
+    // bound; every nesting level decodes through the same `Take<&mut dyn Read>`.
+    let reader: &mut dyn Read = reader;
+    let mut limited = reader.take(length);
+    let message = M::decode_from_reader(&mut limited)?;
+
+    if limited.limit() != 0 {
+        // The message stopped early: either the stream ran out, or a zero tag ended it.
+        if limited.read(&mut [0u8; 1])? == 0 {
+            return Err(DecodeError::UnexpectedEof);
+        }
+        return Err(DecodeError::MalformedInput(format!(
+            "Nested message consumed {} of {} bytes",
+            length - limited.limit(),
+            length
+        )));
+    }
 
-    M::decode_from_slice(&buffer)
+    Ok(message)
 }
 
 #[inline]
@@ -572,6 +880,37 @@ where
     Ok(Some(decode_message(reader)?))
 }
 
+/// Decodes consecutive `field_number` entries, leaving the reader on the first other tag.
+#[inline]
+pub fn decode_repeated_message_field<M, R>(
+    field_number: u32,
+    reader: &mut R,
+) -> DecodeResult<Vec<M>>
+where
+    M: Protobuf,
+    R: Read + Seek,
+{
+    let mut messages = Vec::new();
+
+    while let Some(tag) = peek_tag(reader)? {
+        if tag.field_number != field_number {
+            break;
+        }
+        Tag::decode(reader)?;
+
+        if tag.wire_type != WireType::LengthDelimited {
+            return Err(DecodeError::UnexpectedWireType {
+                expected: WireType::LengthDelimited,
+                got: tag.wire_type,
+            });
+        }
+
+        messages.push(decode_message(reader)?);
+    }
+
+    Ok(messages)
+}
+
 #[inline]
 pub fn decode_enum<E, F, R>(reader: &mut R, converter: F) -> DecodeResult<E>
 where
@@ -612,19 +951,114 @@ where
     Ok(Some(decode_enum(reader, converter)?))
 }
 
+/// An enum value that keeps the raw number when the converter does not recognise it,
+/// so values added by newer peers survive a decode instead of failing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnumOrUnknown<E> {
+    Known(E),
+    Unknown(i32),
+}
+
 #[inline]
-pub fn decode_packed<T, F, R>(reader: &mut R, item_decoder: F) -> DecodeResult<Vec<T>>
+pub fn decode_enum_or_unknown<E, F, R>(
+    reader: &mut R,
+    converter: F,
+) -> DecodeResult<EnumOrUnknown<E>>
 where
-    F: Fn(&mut Cursor<&[u8]>) -> DecodeResult<T>,
     R: Read,
+    F: Fn(i32) -> Option<E>,
 {
-    let length = decode_varint(reader)? as usize;
-    let mut buffer = vec![0u8; length];
-    reader.read_exact(&mut buffer)?;
+    let value = decode_int32(reader)?;
+    Ok(converter(value).map_or(EnumOrUnknown::Unknown(value), EnumOrUnknown::Known))
+}
+
+#[inline]
+pub fn decode_enum_or_unknown_field<E, F, R>(
+    field_number: u32,
+    reader: &mut R,
+    converter: F,
+) -> DecodeResult<Option<EnumOrUnknown<E>>>
+where
+    R: Read,
+    F: Fn(i32) -> Option<E>,
+{
+    let tag = match Tag::decode(reader)? {
+        Some(tag) => tag,
+        None => return Ok(None),
+    };
+
+    if tag.field_number != field_number {
+        return Ok(None);
+    }
 
+    if tag.wire_type != WireType::Varint {
+        return Err(DecodeError::UnexpectedWireType {
+            expected: WireType::Varint,
+            got: tag.wire_type,
+        });
+    }
+
+    Ok(Some(decode_enum_or_unknown(reader, converter)?))
+}
+
+#[inline]
+pub fn decode_packed<T, F, R>(reader: &mut R, item_decoder: F) -> DecodeResult<Vec<T>>
+where
+    F: Fn(&mut PackedCursor) -> DecodeResult<T>,
+    R: Read,
+{
     let mut result = Vec::new();
-    let mut cursor = Cursor::new(buffer.as_slice());
+    decode_packed_into(reader, &mut result, item_decoder)?;
+    Ok(result)
+}
+
+#[inline]
+pub fn decode_packed_into<T, F, R>(
+    reader: &mut R,
+    out: &mut Vec<T>,
+    item_decoder: F,
+) -> DecodeResult<()>
+where
+    F: Fn(&mut PackedCursor) -> DecodeResult<T>,
+    R: Read,
+{
+    let mut cursor = Cursor::new(decode_bytes(reader)?);
+    let length = cursor.get_ref().len() as u64;
+
+    while cursor.position() < length {
+        out.push(item_decoder(&mut cursor)?);
+    }
+
+    Ok(())
+}
 
+#[inline]
+pub fn decode_packed_sized<T, F, R>(
+    reader: &mut R,
+    element_size: usize,
+    item_decoder: F,
+) -> DecodeResult<Vec<T>>
+where
+    F: Fn(&mut PackedCursor) -> DecodeResult<T>,
+    R: Read,
+{
+    if element_size == 0 {
+        return Err(DecodeError::MalformedInput(
+            "Packed element size must be non-zero".to_string(),
+        ));
+    }
+
+    let mut cursor = Cursor::new(decode_bytes(reader)?);
+    let length = cursor.get_ref().len();
+
+    if !length.is_multiple_of(element_size) {
+        return Err(DecodeError::MalformedInput(format!(
+            "Packed length {} is not a multiple of element size {}",
+            length, element_size
+        )));
+    }
+
+    let mut result = Vec::with_capacity(length / element_size);
     while cursor.position() < length as u64 {
         result.push(item_decoder(&mut cursor)?);
     }
@@ -639,7 +1073,7 @@ pub fn decode_packed_field<T, F, R>(
     item_decoder: F,
 ) -> DecodeResult<Option<Vec<T>>>
 where
-    F: Fn(&mut Cursor<&[u8]>) -> DecodeResult<T>,
+    F: Fn(&mut PackedCursor) -> DecodeResult<T>,
     R: Read,
 {
     let tag = match Tag::decode(reader)? {
@@ -661,6 +1095,190 @@ where
     Ok(Some(decode_packed(reader, item_decoder)?))
 }
 
+/// Packed varint elements, decoded straight off the slice instead of through a cursor.
+/// `convert` maps each raw varint to the element type.
+#[inline]
+pub fn decode_packed_varint<T, F, R>(reader: &mut R, convert: F) -> DecodeResult<Vec<T>>
+where
+    F: Fn(u64) -> DecodeResult<T>,
+    R: Read,
+{
+    let bytes = decode_bytes(reader)?;
+    // A varint is at most 10 bytes; counting terminators exactly costs more than it saves.
+    let mut result = Vec::with_capacity(bytes.len() / 10);
+    let mut rest = bytes.as_slice();
+
+    while !rest.is_empty() {
+        let (value, next) = decode_varint_slice(rest)?;
+        result.push(convert(value)?);
+        rest = next;
+    }
+
+    Ok(result)
+}
+
+#[inline]
+pub fn decode_packed_sint32<R: Read>(reader: &mut R) -> DecodeResult<Vec<i32>> {
+    decode_packed_varint(reader, |value| Ok(decode_zigzag(value) as i32))
+}
+
+#[inline]
+pub fn decode_packed_sint64<R: Read>(reader: &mut R) -> DecodeResult<Vec<i64>> {
+    decode_packed_varint(reader, |value| Ok(decode_zigzag(value)))
+}
+
+#[inline]
+pub fn decode_packed_enum<E, F, R>(reader: &mut R, converter: F) -> DecodeResult<Vec<E>>
+where
+    R: Read,
+    F: Fn(i32) -> Option<E>,
+{
+    decode_packed_varint(reader, |value| {
+        converter(value as i32).ok_or_else(|| {
+            DecodeError::MalformedInput(format!("Invalid enum value: {}", value as i32))
+        })
+    })
+}
+
+#[inline]
+pub fn decode_packed_enum_field<E, F, R>(
+    field_number: u32,
+    reader: &mut R,
+    converter: F,
+) -> DecodeResult<Option<Vec<E>>>
+where
+    R: Read,
+    F: Fn(i32) -> Option<E>,
+{
+    let tag = match Tag::decode(reader)? {
+        Some(tag) => tag,
+        None => return Ok(None),
+    };
+
+    if tag.field_number != field_number {
+        return Ok(None);
+    }
+
+    if tag.wire_type != WireType::LengthDelimited {
+        return Err(DecodeError::UnexpectedWireType {
+            expected: WireType::LengthDelimited,
+            got: tag.wire_type,
+        });
+    }
+
+    Ok(Some(decode_packed_enum(reader, converter)?))
+}
+
+pub struct PackedIter<T, F> {
+    cursor: PackedCursor,
+    item_decoder: F,
+    failed: bool,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, F> Iterator for PackedIter<T, F>
+where
+    F: Fn(&mut PackedCursor) -> DecodeResult<T>,
+{
+    type Item = DecodeResult<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.cursor.position() >= self.cursor.get_ref().len() as u64 {
+            return None;
+        }
+
+        let item = (self.item_decoder)(&mut self.cursor);
+        if item.is_err() {
+            self.failed = true;
+        }
+
+        Some(item)
+    }
+}
+
+#[inline]
+pub fn decode_packed_iter<T, F, R>(
+    reader: &mut R,
+    item_decoder: F,
+) -> DecodeResult<PackedIter<T, F>>
+where
+    F: Fn(&mut PackedCursor) -> DecodeResult<T>,
+    R: Read,
+{
+    Ok(PackedIter {
+        cursor: Cursor::new(decode_bytes(reader)?),
+        item_decoder,
+        failed: false,
+        _marker: PhantomData,
+    })
+}
+
+#[inline]
+pub fn decode_repeated_scalar<T, F, R>(
+    tag: &Tag,
+    expected: WireType,
+    reader: &mut R,
+    out: &mut Vec<T>,
+    value_decoder: F,
+) -> DecodeResult<()>
+where
+    F: Fn(&mut R) -> DecodeResult<T>,
+    R: Read,
+{
+    if tag.wire_type != expected {
+        return Err(DecodeError::UnexpectedWireType {
+            expected,
+            got: tag.wire_type,
+        });
+    }
+
+    out.push(value_decoder(reader)?);
+    Ok(())
+}
+
+fn decode_map_entry<K, V, KF, VF, R>(
+    reader: &mut R,
+    key_decoder: &KF,
+    value_decoder: &VF,
+) -> DecodeResult<(K, V)>
+where
+    KF: Fn(&mut Cursor<&[u8]>) -> DecodeResult<K>,
+    VF: Fn(&mut Cursor<&[u8]>) -> DecodeResult<V>,
+    R: Read,
+{
+    let entry_buffer = decode_bytes(reader)?;
+
+    let mut entry_cursor = Cursor::new(entry_buffer.as_slice());
+
+    let key_tag = Tag::decode(&mut entry_cursor)?.ok_or(DecodeError::MalformedInput(
+        "Missing key in map entry".to_string(),
+    ))?;
+
+    if key_tag.field_number != 1 {
+        return Err(DecodeError::MalformedInput(
+            "Expected field number 1 for key in map entry".to_string(),
+        ));
+    }
+
+    let key = key_decoder(&mut entry_cursor)?;
+
+    let value_tag = Tag::decode(&mut entry_cursor)?.ok_or(DecodeError::MalformedInput(
+        "Missing value in map entry".to_string(),
+    ))?;
+
+    if value_tag.field_number != 2 {
+        return Err(DecodeError::MalformedInput(
+            "Expected field number 2 for value in map entry".to_string(),
+        ));
+    }
+
+    let value = value_decoder(&mut entry_cursor)?;
+
+    Ok((key, value))
+}
+
+/// Duplicate keys keep the last value seen, matching protobuf; see [`decode_map_strict`]
+/// to reject them instead.
 #[inline]
 pub fn decode_map<K, V, KF, VF, R>(
     reader: &mut R,
@@ -675,55 +1293,55 @@ where
 {
     let mut map = HashMap::new();
 
-    loop {
-        match Tag::decode(reader)? {
-            Some(tag) => {
-                if tag.wire_type != WireType::LengthDelimited {
-                    return Err(DecodeError::UnexpectedWireType {
-                        expected: WireType::LengthDelimited,
-                        got: tag.wire_type,
-                    });
-                }
-
-                let length = decode_varint(reader)? as usize;
-                let mut entry_buffer = vec![0u8; length];
-                reader.read_exact(&mut entry_buffer)?;
-
-                let mut entry_cursor = Cursor::new(entry_buffer.as_slice());
-
-                let key_tag = Tag::decode(&mut entry_cursor)?.ok_or(
-                    DecodeError::MalformedInput("Missing key in map entry".to_string()),
-                )?;
-
-                if key_tag.field_number != 1 {
-                    return Err(DecodeError::MalformedInput(
-                        "Expected field number 1 for key in map entry".to_string(),
-                    ));
-                }
-
-                let key = key_decoder(&mut entry_cursor)?;
-
-                let value_tag = Tag::decode(&mut entry_cursor)?.ok_or(
-                    DecodeError::MalformedInput("Missing value in map entry".to_string()),
-                )?;
-
-                if value_tag.field_number != 2 {
-                    return Err(DecodeError::MalformedInput(
-                        "Expected field number 2 for value in map entry".to_string(),
-                    ));
-                }
-
-                let value = value_decoder(&mut entry_cursor)?;
-
-                map.insert(key, value);
-            }
-            None => break,
+    while let Some(tag) = Tag::decode(reader)? {
+        if tag.wire_type != WireType::LengthDelimited {
+            return Err(DecodeError::UnexpectedWireType {
+                expected: WireType::LengthDelimited,
+                got: tag.wire_type,
+            });
+        }
+
+        let (key, value) = decode_map_entry(reader, &key_decoder, &value_decoder)?;
+        map.insert(key, value);
+    }
+
+    Ok(map)
+}
+
+#[inline]
+pub fn decode_map_strict<K, V, KF, VF, R>(
+    reader: &mut R,
+    key_decoder: KF,
+    value_decoder: VF,
+) -> DecodeResult<HashMap<K, V>>
+where
+    K: Eq + std::hash::Hash,
+    KF: Fn(&mut Cursor<&[u8]>) -> DecodeResult<K>,
+    VF: Fn(&mut Cursor<&[u8]>) -> DecodeResult<V>,
+    R: Read,
+{
+    let mut map = HashMap::new();
+
+    while let Some(tag) = Tag::decode(reader)? {
+        if tag.wire_type != WireType::LengthDelimited {
+            return Err(DecodeError::UnexpectedWireType {
+                expected: WireType::LengthDelimited,
+                got: tag.wire_type,
+            });
+        }
+
+        let (key, value) = decode_map_entry(reader, &key_decoder, &value_decoder)?;
+        if map.insert(key, value).is_some() {
+            return Err(DecodeError::MalformedInput(
+                "Duplicate key in map entry".to_string(),
+            ));
         }
     }
 
     Ok(map)
 }
 
+/// Like [`decode_map`], a repeated key keeps the last value seen.
 #[inline]
 pub fn decode_map_field<K, V, KF, VF, R>(
     field_number: u32,
@@ -739,23 +1357,11 @@ where
 {
     let mut map = HashMap::new();
 
-    loop {
-        let start_pos = match reader.seek(io::SeekFrom::Current(0)) {
-            Ok(pos) => pos,
-            Err(_) => 0,
-        };
-
-        let tag = match Tag::decode(reader)? {
-            Some(tag) => tag,
-            None => break,
-        };
-
+    while let Some(tag) = peek_tag(reader)? {
         if tag.field_number != field_number {
-            if start_pos > 0 {
-                reader.seek(io::SeekFrom::Start(start_pos))?;
-            }
             break;
         }
+        Tag::decode(reader)?;
 
         if tag.wire_type != WireType::LengthDelimited {
             return Err(DecodeError::UnexpectedWireType {
@@ -764,38 +1370,394 @@ where
             });
         }
 
-        let length = decode_varint(reader)? as usize;
-        let mut entry_buffer = vec![0u8; length];
-        reader.read_exact(&mut entry_buffer)?;
+        let (key, value) = decode_map_entry(reader, &key_decoder, &value_decoder)?;
+        map.insert(key, value);
+    }
 
-        let mut entry_cursor = Cursor::new(entry_buffer.as_slice());
+    Ok(map)
+}
 
-        let key_tag = Tag::decode(&mut entry_cursor)?.ok_or(DecodeError::MalformedInput(
-            "Missing key in map entry".to_string(),
-        ))?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Sample;
 
-        if key_tag.field_number != 1 {
-            return Err(DecodeError::MalformedInput(
-                "Expected field number 1 for key in map entry".to_string(),
+    #[test]
+    fn decode_message_field_empty_message_and_input() {
+        let message = decode_message_field::<Sample, _>(2, &mut &[0x12, 0x00][..]).unwrap();
+        assert_eq!(message, Some(Sample::default()));
+
+        let message = decode_message_field::<Sample, _>(2, &mut &[][..]).unwrap();
+        assert_eq!(message, None);
+    }
+
+    // Fixed-width values are little-endian on the wire regardless of host byte order.
+    #[test]
+    fn fixed_width_decoders_read_little_endian() {
+        assert_eq!(
+            decode_fixed32(&mut &[0x78, 0x56, 0x34, 0x12][..]).unwrap(),
+            0x12345678
+        );
+        assert_eq!(
+            decode_fixed64(&mut &[0xEF, 0xCD, 0xAB, 0x90, 0x78, 0x56, 0x34, 0x12][..]).unwrap(),
+            0x1234567890ABCDEF
+        );
+        assert_eq!(
+            decode_sfixed32(&mut &[0xFF, 0xFF, 0xFF, 0xFF][..]).unwrap(),
+            -1
+        );
+        assert_eq!(
+            decode_sfixed64(&mut &[0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF][..]).unwrap(),
+            -2
+        );
+        assert_eq!(
+            decode_float(&mut &[0x00, 0x00, 0x80, 0x3F][..]).unwrap(),
+            1.0
+        );
+        assert_eq!(
+            decode_double(&mut &[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xF0, 0x3F][..]).unwrap(),
+            1.0
+        );
+    }
+
+    #[test]
+    fn fixed_width_decoders_reject_short_input() {
+        assert!(matches!(
+            decode_fixed32(&mut &[0x78, 0x56, 0x34][..]),
+            Err(DecodeError::UnexpectedEof)
+        ));
+        assert!(matches!(
+            decode_double(&mut &[0x00; 7][..]),
+            Err(DecodeError::UnexpectedEof)
+        ));
+        assert!(matches!(
+            decode_sfixed64(&mut &[0xFF, 0xFF, 0xFF][..]),
+            Err(DecodeError::UnexpectedEof)
+        ));
+        assert!(matches!(
+            decode_sfixed32(&mut &[0xFF, 0xFF, 0xFF][..]),
+            Err(DecodeError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn packed_iter_dropped_early_leaves_reader_after_payload() {
+        // Packed [1, 300, 2] followed by an unrelated tag.
+        let input = [0x04, 0x01, 0xAC, 0x02, 0x02, 0x10];
+        let mut reader = &input[..];
+
+        let mut iter = decode_packed_iter(&mut reader, decode_uint64).unwrap();
+        assert_eq!(iter.next().unwrap().unwrap(), 1);
+        drop(iter);
+
+        assert_eq!(reader, &[0x10]);
+    }
+
+    #[test]
+    fn packed_iter_stops_after_error() {
+        // An over-long varint followed by a valid element the iterator must not reach.
+        let mut payload = vec![0xFF; 11];
+        payload.push(0x01);
+        let mut input = vec![payload.len() as u8];
+        input.extend_from_slice(&payload);
+
+        let mut iter = decode_packed_iter(&mut input.as_slice(), decode_uint64).unwrap();
+        assert!(matches!(
+            iter.next(),
+            Some(Err(DecodeError::InvalidVarint { .. }))
+        ));
+        assert!(iter.next().is_none());
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn skip_field_consumes_fixed_width_values() {
+        let input = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0xAA];
+        let mut reader = &input[..];
+        skip_field(WireType::Fixed32, &mut reader).unwrap();
+        assert_eq!(reader, &input[4..]);
+
+        let mut reader = &input[..];
+        skip_field(WireType::Fixed64, &mut reader).unwrap();
+        assert_eq!(reader, &[0xAA]);
+
+        assert!(matches!(
+            skip_field(WireType::Fixed64, &mut &input[..7]),
+            Err(DecodeError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn tenth_varint_byte_may_only_carry_bit_63() {
+        let varint = |last: u8| {
+            let mut bytes = vec![0xFF; 9];
+            bytes.extend_from_slice(&[last, 0xAA]);
+            bytes
+        };
+
+        let accepted = varint(0x01);
+        assert_eq!(decode_varint(&mut &accepted[..]).unwrap(), u64::MAX);
+        assert_eq!(
+            decode_varint_with_len(&mut &accepted[..]).unwrap(),
+            (u64::MAX, 10)
+        );
+        assert_eq!(
+            decode_varint_slice(&accepted).unwrap(),
+            (u64::MAX, &[0xAA][..])
+        );
+
+        for last in [0x02, 0x7F] {
+            let rejected = varint(last);
+            assert!(matches!(
+                decode_varint(&mut &rejected[..]),
+                Err(DecodeError::InvalidVarint { consumed: 10, .. })
+            ));
+            assert!(matches!(
+                decode_varint_with_len(&mut &rejected[..]),
+                Err(DecodeError::InvalidVarint { consumed: 10, .. })
+            ));
+            assert!(matches!(
+                decode_varint_slice(&rejected),
+                Err(DecodeError::InvalidVarint { consumed: 10, .. })
             ));
         }
+    }
 
-        let key = key_decoder(&mut entry_cursor)?;
-
-        let value_tag = Tag::decode(&mut entry_cursor)?.ok_or(DecodeError::MalformedInput(
-            "Missing value in map entry".to_string(),
-        ))?;
+    #[test]
+    fn tags_above_max_field_number_are_rejected() {
+        let tag_bytes = |field_number: u64| {
+            let mut bytes = Vec::new();
+            encoder::encode_varint(field_number << 3, &mut bytes).unwrap();
+            bytes
+        };
 
-        if value_tag.field_number != 2 {
-            return Err(DecodeError::MalformedInput(
-                "Expected field number 2 for value in map entry".to_string(),
+        let max = tag_bytes(FieldNumber::MAX as u64);
+        let expected = Tag {
+            field_number: FieldNumber::MAX,
+            wire_type: WireType::Varint,
+        };
+        assert_eq!(Tag::decode(&mut &max[..]).unwrap(), Some(expected));
+        assert_eq!(decode_tag_slice(&max).unwrap(), (Some(expected), &[][..]));
+
+        for field_number in [1 << 29, u32::MAX as u64 + 1] {
+            let bytes = tag_bytes(field_number);
+            assert!(matches!(
+                Tag::decode(&mut &bytes[..]),
+                Err(DecodeError::InvalidTag)
+            ));
+            assert!(matches!(
+                decode_tag_slice(&bytes),
+                Err(DecodeError::InvalidTag)
             ));
         }
+    }
 
-        let value = value_decoder(&mut entry_cursor)?;
+    #[test]
+    fn duplicate_map_keys_keep_last_value() {
+        // map<uint32, uint32> field 3 = {1: 10, 2: 20, 1: 30}, then an unrelated field 4.
+        let mut input = Vec::new();
+        for (key, value) in [(1, 10), (2, 20), (1, 30)] {
+            input.extend_from_slice(&[0x1A, 0x04, 0x08, key, 0x10, value]);
+        }
+        let map_len = input.len();
+        input.extend_from_slice(&[0x20, 0x01]);
+
+        let expected = HashMap::from([(1, 30), (2, 20)]);
+
+        let map = decode_map(
+            &mut &input[..map_len],
+            |c| decode_uint32(c),
+            |c| decode_uint32(c),
+        )
+        .unwrap();
+        assert_eq!(map, expected);
+
+        let mut cursor = Cursor::new(&input[..]);
+        let map =
+            decode_map_field(3, &mut cursor, |c| decode_uint32(c), |c| decode_uint32(c)).unwrap();
+        assert_eq!(map, expected);
+        assert_eq!(cursor.position() as usize, map_len);
+    }
 
-        map.insert(key, value);
+    #[test]
+    fn decode_packed_sized_rejects_zero_element_size() {
+        let input = [0x04, 0x01, 0x00, 0x00, 0x00];
+        let mut reader = &input[..];
+        assert!(matches!(
+            decode_packed_sized(&mut reader, 0, decode_fixed32),
+            Err(DecodeError::MalformedInput(_))
+        ));
+        // Rejected before the payload is read.
+        assert_eq!(reader, &input[..]);
     }
 
-    Ok(map)
+    // Keeps only the `name` field (2) of a `Sample` and skips the rest unread.
+    #[derive(Default)]
+    struct NameOnly {
+        names: Vec<String>,
+    }
+
+    impl FieldVisitor for NameOnly {
+        fn visit_field(&mut self, tag: Tag, value: WireValue) -> DecodeResult<()> {
+            assert_eq!(tag.field_number, 2, "skipped field was visited");
+            if let WireValue::LengthDelimited(bytes) = value {
+                self.names.push(String::from_utf8(bytes).unwrap());
+            }
+            Ok(())
+        }
+
+        fn wants_field(&self, tag: Tag) -> bool {
+            tag.field_number == 2
+        }
+    }
+
+    #[test]
+    fn visitor_skips_unwanted_fields() {
+        let sample = Sample {
+            score: 2.5,
+            ..Sample::new(300, "amia").with_child(Sample::new(7, "child"))
+        };
+        let bytes = sample.encode_to_vec();
+
+        let mut visitor = NameOnly::default();
+        decode_with_visitor(&mut &bytes[..], &mut visitor).unwrap();
+        assert_eq!(visitor.names, ["amia"]);
+    }
+
+    #[test]
+    fn packed_sint64_round_trips_extremes() {
+        let values = [0, 1, -1, 63, -64, 1 << 40, -(1 << 40), i64::MIN, i64::MAX];
+        let mut bytes = Vec::new();
+        encoder::encode_packed(6, &values, &mut bytes, |value, buffer| {
+            encoder::encode_zigzag(*value, buffer)
+        })
+        .unwrap();
+
+        let mut reader = &bytes[..];
+        let tag = Tag::decode(&mut reader).unwrap().unwrap();
+        assert_eq!(tag.field_number, 6);
+        assert_eq!(tag.wire_type, WireType::LengthDelimited);
+        assert_eq!(decode_packed_sint64(&mut reader).unwrap(), values);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn finite_decoders_reject_nan_and_accept_normal_values() {
+        let nan_double = [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xF8, 0x7F];
+        assert!(decode_double(&mut &nan_double[..]).unwrap().is_nan());
+        assert!(matches!(
+            decode_double_finite(&mut &nan_double[..]),
+            Err(DecodeError::MalformedInput(_))
+        ));
+        let normal_double = [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xF8, 0x3F];
+        assert_eq!(decode_double_finite(&mut &normal_double[..]).unwrap(), 1.5);
+
+        let nan_float = [0x00, 0x00, 0xC0, 0x7F];
+        assert!(decode_float(&mut &nan_float[..]).unwrap().is_nan());
+        assert!(matches!(
+            decode_float_finite(&mut &nan_float[..]),
+            Err(DecodeError::MalformedInput(_))
+        ));
+        let infinite_float = [0x00, 0x00, 0x80, 0x7F];
+        assert!(matches!(
+            decode_float_finite(&mut &infinite_float[..]),
+            Err(DecodeError::MalformedInput(_))
+        ));
+        let normal_float = [0x00, 0x00, 0xC0, 0x3F];
+        assert_eq!(decode_float_finite(&mut &normal_float[..]).unwrap(), 1.5);
+    }
+
+    #[test]
+    fn decode_message_handles_recursive_types() {
+        let sample = Sample::new(1, "root")
+            .with_child(Sample::new(2, "middle").with_child(Sample::new(3, "leaf")));
+        let mut bytes = Vec::new();
+        encoder::encode_message(5, &sample, &mut bytes).unwrap();
+        encoder::encode_message(5, &Sample::new(4, "next"), &mut bytes).unwrap();
+
+        let mut reader = &bytes[1..];
+        assert_eq!(decode_message::<Sample, _>(&mut reader).unwrap(), sample);
+
+        let messages: Vec<Sample> =
+            decode_repeated_message_field(5, &mut Cursor::new(&bytes[..])).unwrap();
+        assert_eq!(messages, [sample, Sample::new(4, "next")]);
+    }
+
+    #[test]
+    fn decode_message_checks_declared_length() {
+        // A zero tag ends the nested message two bytes before its declared length.
+        assert!(matches!(
+            decode_message::<Sample, _>(&mut &[0x03, 0x00, 0x08, 0x01][..]),
+            Err(DecodeError::MalformedInput(_))
+        ));
+        // The stream ends before the declared length is reached.
+        assert!(matches!(
+            decode_message::<Sample, _>(&mut &[0x04, 0x08, 0x01][..]),
+            Err(DecodeError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn decode_map_strict_rejects_duplicate_keys() {
+        // map<uint32, uint32> = {1: 10, 2: 20}, then {1: 10, 1: 30}.
+        let entry = |key: u8, value: u8| [0x1A, 0x04, 0x08, key, 0x10, value];
+
+        let unique = [entry(1, 10), entry(2, 20)].concat();
+        let map = decode_map_strict(&mut &unique[..], |c| decode_uint32(c), |c| decode_uint32(c))
+            .unwrap();
+        assert_eq!(map, HashMap::from([(1, 10), (2, 20)]));
+
+        let duplicated = [entry(1, 10), entry(1, 30)].concat();
+        assert!(matches!(
+            decode_map_strict(
+                &mut &duplicated[..],
+                |c| decode_uint32(c),
+                |c| decode_uint32(c),
+            ),
+            Err(DecodeError::MalformedInput(_))
+        ));
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum MyEnum {
+        Unset = 0,
+        Active = 1,
+        Negative = -1,
+    }
+
+    impl From<MyEnum> for i32 {
+        fn from(value: MyEnum) -> Self {
+            value as i32
+        }
+    }
+
+    fn my_enum(value: i32) -> Option<MyEnum> {
+        match value {
+            0 => Some(MyEnum::Unset),
+            1 => Some(MyEnum::Active),
+            -1 => Some(MyEnum::Negative),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn decode_packed_enum_field_reads_repeated_enum() {
+        let values = [MyEnum::Active, MyEnum::Negative, MyEnum::Unset];
+        let mut bytes = Vec::new();
+        encoder::encode_packed_enum(4, &values, &mut bytes).unwrap();
+
+        let decoded = decode_packed_enum_field(4, &mut &bytes[..], my_enum).unwrap();
+        assert_eq!(decoded.as_deref(), Some(&values[..]));
+        assert_eq!(
+            decode_packed_enum_field(5, &mut &bytes[..], my_enum).unwrap(),
+            None
+        );
+
+        // Packed field 4 holding [1, 7]; 7 is not a MyEnum value.
+        let unknown = [0x22, 0x02, 0x01, 0x07];
+        assert!(matches!(
+            decode_packed_enum_field(4, &mut &unknown[..], my_enum),
+            Err(DecodeError::MalformedInput(_))
+        ));
+    }
 }
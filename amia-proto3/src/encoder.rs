@@ -1,4 +1,4 @@
-use crate::{Protobuf, WireType};
+use crate::{IntoFieldNumber, Protobuf, WireType};
 use std::collections::HashMap;
 use std::io::{self, Write};
 
@@ -8,7 +8,7 @@ pub const fn make_tag(field_number: u32, wire_type: WireType) -> u32 {
 }
 
 #[inline]
-pub fn encode_varint<W: Write>(value: u64, writer: &mut W) -> io::Result<()> {
+pub fn encode_varint<W: Write + ?Sized>(value: u64, writer: &mut W) -> io::Result<()> {
     let mut value = value;
     while value >= 0x80 {
         writer.write_all(&[(value as u8) | 0x80])?;
@@ -19,22 +19,29 @@ pub fn encode_varint<W: Write>(value: u64, writer: &mut W) -> io::Result<()> {
 }
 
 #[inline]
-pub fn encode_zigzag<W: Write>(value: i64, writer: &mut W) -> io::Result<()> {
+pub fn encode_zigzag<W: Write + ?Sized>(value: i64, writer: &mut W) -> io::Result<()> {
     let zigzag = ((value << 1) ^ (value >> 63)) as u64;
     encode_varint(zigzag, writer)
 }
 
 #[inline]
-pub fn encode_tag<W: Write>(
-    field_number: u32,
+pub fn encode_tag<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
     wire_type: WireType,
     writer: &mut W,
 ) -> io::Result<()> {
-    encode_varint(make_tag(field_number, wire_type) as u64, writer)
+    encode_varint(
+        make_tag(field_number.into_field_number(), wire_type) as u64,
+        writer,
+    )
 }
 
 #[inline]
-pub fn encode_uint32<W: Write>(field_number: u32, value: u32, writer: &mut W) -> io::Result<()> {
+pub fn encode_uint32<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: u32,
+    writer: &mut W,
+) -> io::Result<()> {
     if value == 0 {
         return Ok(());
     }
@@ -43,7 +50,11 @@ pub fn encode_uint32<W: Write>(field_number: u32, value: u32, writer: &mut W) ->
 }
 
 #[inline]
-pub fn encode_int32<W: Write>(field_number: u32, value: i32, writer: &mut W) -> io::Result<()> {
+pub fn encode_int32<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: i32,
+    writer: &mut W,
+) -> io::Result<()> {
     if value == 0 {
         return Ok(());
     }
@@ -52,7 +63,11 @@ pub fn encode_int32<W: Write>(field_number: u32, value: i32, writer: &mut W) ->
 }
 
 #[inline]
-pub fn encode_int64<W: Write>(field_number: u32, value: i64, writer: &mut W) -> io::Result<()> {
+pub fn encode_int64<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: i64,
+    writer: &mut W,
+) -> io::Result<()> {
     if value == 0 {
         return Ok(());
     }
@@ -61,7 +76,11 @@ pub fn encode_int64<W: Write>(field_number: u32, value: i64, writer: &mut W) ->
 }
 
 #[inline]
-pub fn encode_uint64<W: Write>(field_number: u32, value: u64, writer: &mut W) -> io::Result<()> {
+pub fn encode_uint64<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: u64,
+    writer: &mut W,
+) -> io::Result<()> {
     if value == 0 {
         return Ok(());
     }
@@ -70,7 +89,11 @@ pub fn encode_uint64<W: Write>(field_number: u32, value: u64, writer: &mut W) ->
 }
 
 #[inline]
-pub fn encode_sint32<W: Write>(field_number: u32, value: i32, writer: &mut W) -> io::Result<()> {
+pub fn encode_sint32<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: i32,
+    writer: &mut W,
+) -> io::Result<()> {
     if value == 0 {
         return Ok(());
     }
@@ -79,7 +102,11 @@ pub fn encode_sint32<W: Write>(field_number: u32, value: i32, writer: &mut W) ->
 }
 
 #[inline]
-pub fn encode_sint64<W: Write>(field_number: u32, value: i64, writer: &mut W) -> io::Result<()> {
+pub fn encode_sint64<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: i64,
+    writer: &mut W,
+) -> io::Result<()> {
     if value == 0 {
         return Ok(());
     }
@@ -88,7 +115,11 @@ pub fn encode_sint64<W: Write>(field_number: u32, value: i64, writer: &mut W) ->
 }
 
 #[inline]
-pub fn encode_bool<W: Write>(field_number: u32, value: bool, writer: &mut W) -> io::Result<()> {
+pub fn encode_bool<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: bool,
+    writer: &mut W,
+) -> io::Result<()> {
     if !value {
         return Ok(());
     }
@@ -97,7 +128,21 @@ pub fn encode_bool<W: Write>(field_number: u32, value: bool, writer: &mut W) ->
 }
 
 #[inline]
-pub fn encode_string<W: Write>(field_number: u32, value: &str, writer: &mut W) -> io::Result<()> {
+pub fn encode_bool_present<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: bool,
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_tag(field_number, WireType::Varint, writer)?;
+    writer.write_all(&[value as u8])
+}
+
+#[inline]
+pub fn encode_string<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: &str,
+    writer: &mut W,
+) -> io::Result<()> {
     if value.is_empty() {
         return Ok(());
     }
@@ -107,7 +152,22 @@ pub fn encode_string<W: Write>(field_number: u32, value: &str, writer: &mut W) -
 }
 
 #[inline]
-pub fn encode_bytes<W: Write>(field_number: u32, value: &[u8], writer: &mut W) -> io::Result<()> {
+pub fn encode_string_present<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: &str,
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_tag(field_number, WireType::LengthDelimited, writer)?;
+    encode_varint(value.len() as u64, writer)?;
+    writer.write_all(value.as_bytes())
+}
+
+#[inline]
+pub fn encode_bytes<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: &[u8],
+    writer: &mut W,
+) -> io::Result<()> {
     if value.is_empty() {
         return Ok(());
     }
@@ -117,7 +177,22 @@ pub fn encode_bytes<W: Write>(field_number: u32, value: &[u8], writer: &mut W) -
 }
 
 #[inline]
-pub fn encode_float<W: Write>(field_number: u32, value: f32, writer: &mut W) -> io::Result<()> {
+pub fn encode_bytes_present<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: &[u8],
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_tag(field_number, WireType::LengthDelimited, writer)?;
+    encode_varint(value.len() as u64, writer)?;
+    writer.write_all(value)
+}
+
+#[inline]
+pub fn encode_float<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: f32,
+    writer: &mut W,
+) -> io::Result<()> {
     if value == 0.0 {
         return Ok(());
     }
@@ -126,7 +201,11 @@ pub fn encode_float<W: Write>(field_number: u32, value: f32, writer: &mut W) ->
 }
 
 #[inline]
-pub fn encode_double<W: Write>(field_number: u32, value: f64, writer: &mut W) -> io::Result<()> {
+pub fn encode_double<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: f64,
+    writer: &mut W,
+) -> io::Result<()> {
     if value == 0.0 {
         return Ok(());
     }
@@ -135,7 +214,11 @@ pub fn encode_double<W: Write>(field_number: u32, value: f64, writer: &mut W) ->
 }
 
 #[inline]
-pub fn encode_fixed32<W: Write>(field_number: u32, value: u32, writer: &mut W) -> io::Result<()> {
+pub fn encode_fixed32<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: u32,
+    writer: &mut W,
+) -> io::Result<()> {
     if value == 0 {
         return Ok(());
     }
@@ -144,7 +227,11 @@ pub fn encode_fixed32<W: Write>(field_number: u32, value: u32, writer: &mut W) -
 }
 
 #[inline]
-pub fn encode_fixed64<W: Write>(field_number: u32, value: u64, writer: &mut W) -> io::Result<()> {
+pub fn encode_fixed64<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: u64,
+    writer: &mut W,
+) -> io::Result<()> {
     if value == 0 {
         return Ok(());
     }
@@ -152,8 +239,39 @@ pub fn encode_fixed64<W: Write>(field_number: u32, value: u64, writer: &mut W) -
     writer.write_all(&value.to_le_bytes())
 }
 
+// Non-standard big-endian fixed fields, for servers that do not follow the protobuf spec.
+#[inline]
+pub fn encode_fixed32_be<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: u32,
+    writer: &mut W,
+) -> io::Result<()> {
+    if value == 0 {
+        return Ok(());
+    }
+    encode_tag(field_number, WireType::Fixed32, writer)?;
+    writer.write_all(&value.to_be_bytes())
+}
+
+#[inline]
+pub fn encode_fixed64_be<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: u64,
+    writer: &mut W,
+) -> io::Result<()> {
+    if value == 0 {
+        return Ok(());
+    }
+    encode_tag(field_number, WireType::Fixed64, writer)?;
+    writer.write_all(&value.to_be_bytes())
+}
+
 #[inline]
-pub fn encode_sfixed32<W: Write>(field_number: u32, value: i32, writer: &mut W) -> io::Result<()> {
+pub fn encode_sfixed32<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: i32,
+    writer: &mut W,
+) -> io::Result<()> {
     if value == 0 {
         return Ok(());
     }
@@ -162,7 +280,11 @@ pub fn encode_sfixed32<W: Write>(field_number: u32, value: i32, writer: &mut W)
 }
 
 #[inline]
-pub fn encode_sfixed64<W: Write>(field_number: u32, value: i64, writer: &mut W) -> io::Result<()> {
+pub fn encode_sfixed64<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: i64,
+    writer: &mut W,
+) -> io::Result<()> {
     if value == 0 {
         return Ok(());
     }
@@ -171,31 +293,219 @@ pub fn encode_sfixed64<W: Write>(field_number: u32, value: i64, writer: &mut W)
 }
 
 #[inline]
-pub fn encode_enum<W: Write, E: Into<i32>>(
-    field_number: u32,
+pub fn encode_enum<W: Write + ?Sized, E: Into<i32>>(
+    field_number: impl IntoFieldNumber,
     value: E,
     writer: &mut W,
 ) -> io::Result<()> {
     encode_int32(field_number, value.into(), writer)
 }
 
+// Oneof members are always written, even when they hold the default value, because
+// their presence selects the oneof case. Do not use the default-skipping encoders for them.
+
+#[inline]
+pub fn encode_oneof_uint32<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: u32,
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_tag(field_number, WireType::Varint, writer)?;
+    encode_varint(value as u64, writer)
+}
+
+#[inline]
+pub fn encode_oneof_int32<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: i32,
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_tag(field_number, WireType::Varint, writer)?;
+    encode_varint(value as u64, writer)
+}
+
+#[inline]
+pub fn encode_oneof_int64<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: i64,
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_tag(field_number, WireType::Varint, writer)?;
+    encode_varint(value as u64, writer)
+}
+
+#[inline]
+pub fn encode_oneof_uint64<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: u64,
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_tag(field_number, WireType::Varint, writer)?;
+    encode_varint(value, writer)
+}
+
+#[inline]
+pub fn encode_oneof_sint32<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: i32,
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_tag(field_number, WireType::Varint, writer)?;
+    encode_zigzag(value as i64, writer)
+}
+
+#[inline]
+pub fn encode_oneof_sint64<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: i64,
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_tag(field_number, WireType::Varint, writer)?;
+    encode_zigzag(value, writer)
+}
+
+#[inline]
+pub fn encode_oneof_bool<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: bool,
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_bool_present(field_number, value, writer)
+}
+
+#[inline]
+pub fn encode_oneof_string<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: &str,
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_string_present(field_number, value, writer)
+}
+
+#[inline]
+pub fn encode_oneof_bytes<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: &[u8],
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_bytes_present(field_number, value, writer)
+}
+
+#[inline]
+pub fn encode_oneof_float<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: f32,
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_tag(field_number, WireType::Fixed32, writer)?;
+    writer.write_all(&value.to_le_bytes())
+}
+
+#[inline]
+pub fn encode_oneof_double<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: f64,
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_tag(field_number, WireType::Fixed64, writer)?;
+    writer.write_all(&value.to_le_bytes())
+}
+
+#[inline]
+pub fn encode_oneof_fixed32<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: u32,
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_tag(field_number, WireType::Fixed32, writer)?;
+    writer.write_all(&value.to_le_bytes())
+}
+
+#[inline]
+pub fn encode_oneof_fixed64<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: u64,
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_tag(field_number, WireType::Fixed64, writer)?;
+    writer.write_all(&value.to_le_bytes())
+}
+
+#[inline]
+pub fn encode_oneof_sfixed32<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: i32,
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_tag(field_number, WireType::Fixed32, writer)?;
+    writer.write_all(&value.to_le_bytes())
+}
+
+#[inline]
+pub fn encode_oneof_sfixed64<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: i64,
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_tag(field_number, WireType::Fixed64, writer)?;
+    writer.write_all(&value.to_le_bytes())
+}
+
+#[inline]
+pub fn encode_oneof_enum<W: Write + ?Sized, E: Into<i32>>(
+    field_number: impl IntoFieldNumber,
+    value: E,
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_oneof_int32(field_number, value.into(), writer)
+}
+
 #[inline]
 pub fn encode_repeated<T, F, W>(
-    field_number: u32,
+    field_number: impl IntoFieldNumber,
     values: &[T],
     writer: &mut W,
     encoder: F,
 ) -> io::Result<()>
 where
     F: Fn(u32, &T, &mut W) -> io::Result<()>,
-    W: Write,
+    W: Write + ?Sized,
 {
+    let field_number = field_number.into_field_number();
     for value in values {
         encoder(field_number, value, writer)?;
     }
     Ok(())
 }
 
+// Packs scalar wire types (the proto3 default) and falls back to one tag per element
+// for length-delimited values, which cannot be packed. `value_writer_fn` writes whatever
+// follows the tag: the bare scalar, or the length prefix and payload.
+#[inline]
+pub fn encode_repeated_auto<T, W>(
+    field_number: impl IntoFieldNumber,
+    values: &[T],
+    wire_type: WireType,
+    writer: &mut W,
+    value_writer_fn: impl Fn(&T, &mut Vec<u8>) -> io::Result<()>,
+) -> io::Result<()>
+where
+    W: Write + ?Sized,
+{
+    if wire_type != WireType::LengthDelimited {
+        return encode_packed(field_number, values, writer, value_writer_fn);
+    }
+
+    let mut buffer = Vec::new();
+    for value in values {
+        buffer.clear();
+        value_writer_fn(value, &mut buffer)?;
+        encode_tag(field_number, wire_type, writer)?;
+        writer.write_all(&buffer)?;
+    }
+    Ok(())
+}
+
 // #[inline]
 // pub fn encode_packed<T, F, W>(
 //     field_number: u32,
@@ -230,13 +540,13 @@ where
 
 #[inline]
 pub fn encode_packed<T, W>(
-    field_number: u32,
+    field_number: impl IntoFieldNumber,
     values: &[T],
     writer: &mut W,
     value_writer_fn: impl Fn(&T, &mut Vec<u8>) -> io::Result<()>,
 ) -> io::Result<()>
 where
-    W: Write,
+    W: Write + ?Sized,
 {
     if values.is_empty() {
         return Ok(());
@@ -253,15 +563,94 @@ where
 }
 
 #[inline]
-pub fn encode_message<W: Write, M: Protobuf>(
-    field_number: u32,
-    message: &M,
+pub fn encode_packed_sized<T, W>(
+    field_number: impl IntoFieldNumber,
+    values: &[T],
+    element_size: usize,
+    writer: &mut W,
+    value_writer_fn: impl Fn(&T, &mut Vec<u8>) -> io::Result<()>,
+) -> io::Result<()>
+where
+    W: Write + ?Sized,
+{
+    if values.is_empty() {
+        return Ok(());
+    }
+
+    let mut buffer = Vec::with_capacity(values.len() * element_size);
+    for value in values {
+        value_writer_fn(value, &mut buffer)?;
+    }
+
+    encode_tag(field_number, WireType::LengthDelimited, writer)?;
+    encode_varint(buffer.len() as u64, writer)?;
+    writer.write_all(&buffer)
+}
+
+#[inline]
+pub fn encode_packed_enum<W, E>(
+    field_number: impl IntoFieldNumber,
+    values: &[E],
     writer: &mut W,
+) -> io::Result<()>
+where
+    W: Write + ?Sized,
+    E: Into<i32> + Copy,
+{
+    encode_packed(field_number, values, writer, |value, buffer| {
+        encode_varint((*value).into() as u64, buffer)
+    })
+}
+
+#[inline]
+pub fn encode_message<W: Write + ?Sized, M: Protobuf>(
+    field_number: impl IntoFieldNumber,
+    message: &M,
+    mut writer: &mut W,
 ) -> io::Result<()> {
     encode_tag(field_number, WireType::LengthDelimited, writer)?;
-    let encoded = message.encode_to_vec();
-    encode_varint(encoded.len() as u64, writer)?;
-    writer.write_all(&encoded)
+    // Without a cheap `encoded_len`, measuring would serialize the message (and, recursively,
+    // every nested one) a second time, so buffer it once instead.
+    if M::CHEAP_ENCODED_LEN {
+        encode_varint(message.encoded_len() as u64, writer)?;
+        // Erased so that recursive message types don't instantiate ever-deeper writer types.
+        let mut writer: &mut dyn Write = &mut writer;
+        message.encode_to_writer(&mut writer)
+    } else {
+        let encoded = message.encode_to_vec();
+        encode_varint(encoded.len() as u64, writer)?;
+        writer.write_all(&encoded)
+    }
+}
+
+// The requested name for `encode_message`'s streaming path, which is taken when
+// `M::CHEAP_ENCODED_LEN` is set; otherwise the message is buffered once. Measuring through the
+// default `encoded_len` here would serialize the innermost of `d` nested messages 2^d times.
+#[inline]
+pub fn encode_message_prefixed<W: Write + ?Sized, M: Protobuf>(
+    field_number: impl IntoFieldNumber,
+    message: &M,
+    writer: &mut W,
+) -> io::Result<()> {
+    encode_message(field_number, message, writer)
+}
+
+#[inline]
+pub fn encode_repeated_message<W: Write + ?Sized, M: Protobuf>(
+    field_number: impl IntoFieldNumber,
+    messages: &[M],
+    writer: &mut W,
+) -> io::Result<()> {
+    let mut scratch = Vec::new();
+    for message in messages {
+        scratch.clear();
+        message.encode_to_writer(&mut scratch)?;
+
+        encode_tag(field_number, WireType::LengthDelimited, writer)?;
+        encode_varint(scratch.len() as u64, writer)?;
+        writer.write_all(&scratch)?;
+    }
+    Ok(())
 }
 
 // #[inline]
@@ -299,18 +688,35 @@ pub fn encode_message<W: Write, M: Protobuf>(
 
 #[inline]
 pub fn encode_map<K, V, W, IK, IV>(
-    field_number: u32,
+    field_number: impl IntoFieldNumber,
     map: HashMap<K, V>,
     writer: &mut W,
+    key_encoder: IK,
+    value_encoder: IV,
+) -> io::Result<()>
+where
+    W: Write + ?Sized,
+    IK: FnMut(u32, &K, &mut Vec<u8>) -> io::Result<()>,
+    IV: FnMut(u32, &V, &mut Vec<u8>) -> io::Result<()>,
+{
+    encode_map_ordered(field_number, map, writer, key_encoder, value_encoder)
+}
+
+/// Emits entries in iteration order, for peers that depend on map entry order.
+#[inline]
+pub fn encode_map_ordered<K, V, W, IK, IV>(
+    field_number: impl IntoFieldNumber,
+    entries: impl IntoIterator<Item = (K, V)>,
+    writer: &mut W,
     mut key_encoder: IK,
     mut value_encoder: IV,
 ) -> io::Result<()>
 where
-    W: Write,
+    W: Write + ?Sized,
     IK: FnMut(u32, &K, &mut Vec<u8>) -> io::Result<()>,
     IV: FnMut(u32, &V, &mut Vec<u8>) -> io::Result<()>,
 {
-    for (key, value) in map {
+    for (key, value) in entries {
         let mut entry_buf = Vec::new();
         key_encoder(1, &key, &mut entry_buf)?;
         value_encoder(2, &value, &mut entry_buf)?;
@@ -338,214 +744,460 @@ pub fn size_of_varint(value: u64) -> usize {
     }
 }
 
-// #[inline]
-// pub fn size_of_tag(field_number: u32) -> usize {
-//     size_of_varint(make_tag(field_number, WireType::Varint) as u64)
-// }
+#[inline]
+pub fn size_of_tag(field_number: impl IntoFieldNumber) -> usize {
+    size_of_varint(make_tag(field_number.into_field_number(), WireType::Varint) as u64)
+}
 
-// #[inline]
-// pub fn size_of_zigzag(value: i64) -> usize {
-//     let zigzag = ((value << 1) ^ (value >> 63)) as u64;
-//     size_of_varint(zigzag)
-// }
+#[inline]
+pub fn size_of_zigzag(value: i64) -> usize {
+    let zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    size_of_varint(zigzag)
+}
 
-// #[inline]
-// pub fn size_of_uint32(field_number: u32, value: u32) -> usize {
-//     if value == 0 {
-//         return 0;
-//     }
-//     size_of_tag(field_number) + size_of_varint(value as u64)
-// }
+#[inline]
+pub fn size_of_uint32(field_number: impl IntoFieldNumber, value: u32) -> usize {
+    if value == 0 {
+        return 0;
+    }
+    size_of_tag(field_number) + size_of_varint(value as u64)
+}
 
-// #[inline]
-// pub fn size_of_int32(field_number: u32, value: i32) -> usize {
-//     if value == 0 {
-//         return 0;
-//     }
-//     size_of_tag(field_number) + size_of_varint(value as u64)
-// }
+#[inline]
+pub fn size_of_int32(field_number: impl IntoFieldNumber, value: i32) -> usize {
+    if value == 0 {
+        return 0;
+    }
+    size_of_tag(field_number) + size_of_varint(value as u64)
+}
 
-// #[inline]
-// pub fn size_of_int64(field_number: u32, value: i64) -> usize {
-//     if value == 0 {
-//         return 0;
-//     }
-//     size_of_tag(field_number) + size_of_varint(value as u64)
-// }
+#[inline]
+pub fn size_of_int64(field_number: impl IntoFieldNumber, value: i64) -> usize {
+    if value == 0 {
+        return 0;
+    }
+    size_of_tag(field_number) + size_of_varint(value as u64)
+}
 
-// #[inline]
-// pub fn size_of_uint64(field_number: u32, value: u64) -> usize {
-//     if value == 0 {
-//         return 0;
-//     }
-//     size_of_tag(field_number) + size_of_varint(value)
-// }
+#[inline]
+pub fn size_of_uint64(field_number: impl IntoFieldNumber, value: u64) -> usize {
+    if value == 0 {
+        return 0;
+    }
+    size_of_tag(field_number) + size_of_varint(value)
+}
 
-// #[inline]
-// pub fn size_of_sint32(field_number: u32, value: i32) -> usize {
-//     if value == 0 {
-//         return 0;
-//     }
-//     size_of_tag(field_number) + size_of_zigzag(value as i64)
-// }
+#[inline]
+pub fn size_of_sint32(field_number: impl IntoFieldNumber, value: i32) -> usize {
+    if value == 0 {
+        return 0;
+    }
+    size_of_tag(field_number) + size_of_zigzag(value as i64)
+}
 
-// #[inline]
-// pub fn size_of_sint64(field_number: u32, value: i64) -> usize {
-//     if value == 0 {
-//         return 0;
-//     }
-//     size_of_tag(field_number) + size_of_zigzag(value)
-// }
+#[inline]
+pub fn size_of_sint64(field_number: impl IntoFieldNumber, value: i64) -> usize {
+    if value == 0 {
+        return 0;
+    }
+    size_of_tag(field_number) + size_of_zigzag(value)
+}
 
-// #[inline]
-// pub fn size_of_bool(field_number: u32, value: bool) -> usize {
-//     if !value {
-//         return 0;
-//     }
-//     size_of_tag(field_number) + 1
-// }
+#[inline]
+pub fn size_of_bool(field_number: impl IntoFieldNumber, value: bool) -> usize {
+    if !value {
+        return 0;
+    }
+    size_of_tag(field_number) + 1
+}
 
-// #[inline]
-// pub fn size_of_string(field_number: u32, value: &str) -> usize {
-//     if value.is_empty() {
-//         return 0;
-//     }
-//     let str_len = value.len();
-//     size_of_tag(field_number) + size_of_varint(str_len as u64) + str_len
-// }
+#[inline]
+pub fn size_of_string(field_number: impl IntoFieldNumber, value: &str) -> usize {
+    if value.is_empty() {
+        return 0;
+    }
+    let str_len = value.len();
+    size_of_tag(field_number) + size_of_varint(str_len as u64) + str_len
+}
 
-// #[inline]
-// pub fn size_of_bytes(field_number: u32, value: &[u8]) -> usize {
-//     if value.is_empty() {
-//         return 0;
-//     }
-//     let bytes_len = value.len();
-//     size_of_tag(field_number) + size_of_varint(bytes_len as u64) + bytes_len
-// }
+#[inline]
+pub fn size_of_bytes(field_number: impl IntoFieldNumber, value: &[u8]) -> usize {
+    if value.is_empty() {
+        return 0;
+    }
+    let bytes_len = value.len();
+    size_of_tag(field_number) + size_of_varint(bytes_len as u64) + bytes_len
+}
 
-// #[inline]
-// pub fn size_of_float(field_number: u32, value: f32) -> usize {
-//     if value == 0.0 {
-//         return 0;
-//     }
-//     size_of_tag(field_number) + 4
-// }
+#[inline]
+pub fn size_of_float(field_number: impl IntoFieldNumber, value: f32) -> usize {
+    if value == 0.0 {
+        return 0;
+    }
+    size_of_tag(field_number) + 4
+}
 
-// #[inline]
-// pub fn size_of_double(field_number: u32, value: f64) -> usize {
-//     if value == 0.0 {
-//         return 0;
-//     }
-//     size_of_tag(field_number) + 8
-// }
+#[inline]
+pub fn size_of_double(field_number: impl IntoFieldNumber, value: f64) -> usize {
+    if value == 0.0 {
+        return 0;
+    }
+    size_of_tag(field_number) + 8
+}
 
-// #[inline]
-// pub fn size_of_fixed32(field_number: u32, value: u32) -> usize {
-//     if value == 0 {
-//         return 0;
-//     }
-//     size_of_tag(field_number) + 4
-// }
+#[inline]
+pub fn size_of_fixed32(field_number: impl IntoFieldNumber, value: u32) -> usize {
+    if value == 0 {
+        return 0;
+    }
+    size_of_tag(field_number) + 4
+}
 
-// #[inline]
-// pub fn size_of_fixed64(field_number: u32, value: u64) -> usize {
-//     if value == 0 {
-//         return 0;
-//     }
-//     size_of_tag(field_number) + 8
-// }
+#[inline]
+pub fn size_of_fixed64(field_number: impl IntoFieldNumber, value: u64) -> usize {
+    if value == 0 {
+        return 0;
+    }
+    size_of_tag(field_number) + 8
+}
 
-// #[inline]
-// pub fn size_of_sfixed32(field_number: u32, value: i32) -> usize {
-//     if value == 0 {
-//         return 0;
-//     }
-//     size_of_tag(field_number) + 4
-// }
+#[inline]
+pub fn size_of_sfixed32(field_number: impl IntoFieldNumber, value: i32) -> usize {
+    if value == 0 {
+        return 0;
+    }
+    size_of_tag(field_number) + 4
+}
 
-// #[inline]
-// pub fn size_of_sfixed64(field_number: u32, value: i64) -> usize {
-//     if value == 0 {
-//         return 0;
-//     }
-//     size_of_tag(field_number) + 8
-// }
+#[inline]
+pub fn size_of_sfixed64(field_number: impl IntoFieldNumber, value: i64) -> usize {
+    if value == 0 {
+        return 0;
+    }
+    size_of_tag(field_number) + 8
+}
 
-// #[inline]
-// pub fn size_of_enum<E: Into<i32> + Copy>(field_number: u32, value: E) -> usize {
-//     size_of_int32(field_number, value.into())
-// }
+#[inline]
+pub fn size_of_enum<E: Into<i32> + Copy>(field_number: impl IntoFieldNumber, value: E) -> usize {
+    size_of_int32(field_number, value.into())
+}
 
-// #[inline]
-// pub fn size_of_repeated<T, F>(field_number: u32, values: &[T], size_fn: F) -> usize
-// where
-//     F: Fn(u32, &T) -> usize,
-// {
-//     values.iter().map(|v| size_fn(field_number, v)).sum()
-// }
+#[inline]
+pub fn size_of_repeated<T, F>(field_number: impl IntoFieldNumber, values: &[T], size_fn: F) -> usize
+where
+    F: Fn(u32, &T) -> usize,
+{
+    let field_number = field_number.into_field_number();
+    values.iter().map(|v| size_fn(field_number, v)).sum()
+}
 
-// #[inline]
-// pub fn size_of_packed<T, F>(field_number: u32, values: &[T], value_size_fn: F) -> usize
-// where
-//     F: Fn(&T) -> usize,
-// {
-//     if values.is_empty() {
-//         return 0;
-//     }
+#[inline]
+pub fn size_of_packed<T, F>(
+    field_number: impl IntoFieldNumber,
+    values: &[T],
+    value_size_fn: F,
+) -> usize
+where
+    F: Fn(&T) -> usize,
+{
+    if values.is_empty() {
+        return 0;
+    }
 
-//     let content_size: usize = values.iter().map(|v| value_size_fn(v)).sum();
+    let content_size: usize = values.iter().map(value_size_fn).sum();
 
-//     size_of_tag(field_number) + size_of_varint(content_size as u64) + content_size
-// }
+    size_of_tag(field_number) + size_of_varint(content_size as u64) + content_size
+}
 
-// #[inline]
-// pub fn size_of_message<M: Protobuf>(field_number: u32, message: &M) -> usize {
-//     let message_size = message.encoded_len();
-//     if message_size == 0 {
-//         return 0;
-//     }
+#[inline]
+pub fn size_of_message<M: Protobuf>(field_number: impl IntoFieldNumber, message: &M) -> usize {
+    let message_size = message.encoded_len();
+    size_of_tag(field_number) + size_of_varint(message_size as u64) + message_size
+}
 
-//     size_of_tag(field_number) + size_of_varint(message_size as u64) + message_size
-// }
+pub fn size_of_map<'a, K: 'a, V: 'a, SK, SV>(
+    field_number: impl IntoFieldNumber,
+    map: impl IntoIterator<Item = (&'a K, &'a V)>,
+    key_size_fn: SK,
+    value_size_fn: SV,
+) -> usize
+where
+    SK: Fn(u32, &K) -> usize,
+    SV: Fn(u32, &V) -> usize,
+{
+    let mut total_size = 0;
 
-// pub fn size_of_map<'a, K: 'a, V: 'a, SK, SV>(
-//     field_number: u32,
-//     map: impl IntoIterator<Item = (&'a K, &'a V)>,
-//     key_size_fn: SK,
-//     value_size_fn: SV,
-// ) -> usize
-// where
-//     SK: Fn(u32, &K) -> usize,
-//     SV: Fn(u32, &V) -> usize,
-// {
-//     let mut total_size = 0;
+    for (key, value) in map {
+        let key_size = key_size_fn(1, key);
+        let value_size = value_size_fn(2, value);
+        let entry_size = key_size + value_size;
+        total_size += size_of_tag(field_number) + size_of_varint(entry_size as u64) + entry_size;
+    }
 
-//     for (key, value) in map {
-//         let key_size = key_size_fn(1, key);
-//         let value_size = value_size_fn(2, value);
-//         let entry_size = key_size + value_size;
-//         total_size += size_of_tag(field_number) + size_of_varint(entry_size as u64) + entry_size;
-//     }
+    total_size
+}
 
-//     total_size
-// }
+#[inline]
+pub fn size_of_varint_value(value: u64) -> usize {
+    size_of_varint(value)
+}
 
-// #[inline]
-// pub fn size_of_varint_value(value: u64) -> usize {
-//     size_of_varint(value)
-// }
+#[inline]
+pub fn size_of_zigzag_value(value: i64) -> usize {
+    size_of_zigzag(value)
+}
 
-// #[inline]
-// pub fn size_of_zigzag_value(value: i64) -> usize {
-//     size_of_zigzag(value)
-// }
+#[inline]
+pub fn size_of_fixed32_value(_value: u32) -> usize {
+    4
+}
 
-// #[inline]
-// pub fn size_of_fixed32_value(_value: u32) -> usize {
-//     4
-// }
+#[inline]
+pub fn size_of_fixed64_value(_value: u64) -> usize {
+    8
+}
 
-// #[inline]
-// pub fn size_of_fixed64_value(_value: u64) -> usize {
-//     8
-// }
+// Counted variants return the number of bytes written, for building field-offset indexes.
+
+#[inline]
+pub fn encode_uint32_counted<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: u32,
+    writer: &mut W,
+) -> io::Result<usize> {
+    encode_uint32(field_number, value, writer)?;
+    Ok(size_of_uint32(field_number, value))
+}
+
+#[inline]
+pub fn encode_int32_counted<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: i32,
+    writer: &mut W,
+) -> io::Result<usize> {
+    encode_int32(field_number, value, writer)?;
+    Ok(size_of_int32(field_number, value))
+}
+
+#[inline]
+pub fn encode_int64_counted<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: i64,
+    writer: &mut W,
+) -> io::Result<usize> {
+    encode_int64(field_number, value, writer)?;
+    Ok(size_of_int64(field_number, value))
+}
+
+#[inline]
+pub fn encode_uint64_counted<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: u64,
+    writer: &mut W,
+) -> io::Result<usize> {
+    encode_uint64(field_number, value, writer)?;
+    Ok(size_of_uint64(field_number, value))
+}
+
+#[inline]
+pub fn encode_sint32_counted<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: i32,
+    writer: &mut W,
+) -> io::Result<usize> {
+    encode_sint32(field_number, value, writer)?;
+    Ok(size_of_sint32(field_number, value))
+}
+
+#[inline]
+pub fn encode_sint64_counted<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: i64,
+    writer: &mut W,
+) -> io::Result<usize> {
+    encode_sint64(field_number, value, writer)?;
+    Ok(size_of_sint64(field_number, value))
+}
+
+#[inline]
+pub fn encode_bool_counted<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: bool,
+    writer: &mut W,
+) -> io::Result<usize> {
+    encode_bool(field_number, value, writer)?;
+    Ok(size_of_bool(field_number, value))
+}
+
+#[inline]
+pub fn encode_string_counted<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: &str,
+    writer: &mut W,
+) -> io::Result<usize> {
+    encode_string(field_number, value, writer)?;
+    Ok(size_of_string(field_number, value))
+}
+
+#[inline]
+pub fn encode_bytes_counted<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: &[u8],
+    writer: &mut W,
+) -> io::Result<usize> {
+    encode_bytes(field_number, value, writer)?;
+    Ok(size_of_bytes(field_number, value))
+}
+
+#[inline]
+pub fn encode_float_counted<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: f32,
+    writer: &mut W,
+) -> io::Result<usize> {
+    encode_float(field_number, value, writer)?;
+    Ok(size_of_float(field_number, value))
+}
+
+#[inline]
+pub fn encode_double_counted<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: f64,
+    writer: &mut W,
+) -> io::Result<usize> {
+    encode_double(field_number, value, writer)?;
+    Ok(size_of_double(field_number, value))
+}
+
+#[inline]
+pub fn encode_fixed32_counted<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: u32,
+    writer: &mut W,
+) -> io::Result<usize> {
+    encode_fixed32(field_number, value, writer)?;
+    Ok(size_of_fixed32(field_number, value))
+}
+
+#[inline]
+pub fn encode_fixed64_counted<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: u64,
+    writer: &mut W,
+) -> io::Result<usize> {
+    encode_fixed64(field_number, value, writer)?;
+    Ok(size_of_fixed64(field_number, value))
+}
+
+#[inline]
+pub fn encode_sfixed32_counted<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: i32,
+    writer: &mut W,
+) -> io::Result<usize> {
+    encode_sfixed32(field_number, value, writer)?;
+    Ok(size_of_sfixed32(field_number, value))
+}
+
+#[inline]
+pub fn encode_sfixed64_counted<W: Write + ?Sized>(
+    field_number: impl IntoFieldNumber,
+    value: i64,
+    writer: &mut W,
+) -> io::Result<usize> {
+    encode_sfixed64(field_number, value, writer)?;
+    Ok(size_of_sfixed64(field_number, value))
+}
+
+#[inline]
+pub fn encode_enum_counted<W: Write + ?Sized, E: Into<i32> + Copy>(
+    field_number: impl IntoFieldNumber,
+    value: E,
+    writer: &mut W,
+) -> io::Result<usize> {
+    encode_enum(field_number, value, writer)?;
+    Ok(size_of_enum(field_number, value))
+}
+
+#[inline]
+pub fn encode_message_counted<W: Write + ?Sized, M: Protobuf>(
+    field_number: impl IntoFieldNumber,
+    message: &M,
+    writer: &mut W,
+) -> io::Result<usize> {
+    // Measures the buffer it already encoded rather than calling encoded_len.
+    let encoded = message.encode_to_vec();
+    encode_tag(field_number, WireType::LengthDelimited, writer)?;
+    encode_varint(encoded.len() as u64, writer)?;
+    writer.write_all(&encoded)?;
+    Ok(size_of_tag(field_number) + size_of_varint(encoded.len() as u64) + encoded.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder;
+    use crate::test_support::Counted;
+
+    #[test]
+    fn encode_message_serializes_once() {
+        let expected = [0x12, 0x03, 0x08, 0x96, 0x01];
+
+        let cheap = Counted::<true>::new(150);
+        let mut bytes = Vec::new();
+        encode_message(2, &cheap, &mut bytes).unwrap();
+        assert_eq!(bytes, expected);
+        assert_eq!(cheap.writes.get(), 1);
+
+        let measured = Counted::<false>::new(150);
+        let mut bytes = Vec::new();
+        encode_message(2, &measured, &mut bytes).unwrap();
+        assert_eq!(bytes, expected);
+        assert_eq!(measured.writes.get(), 1);
+    }
+
+    #[test]
+    fn encode_message_prefixed_serializes_once_without_cheap_len() {
+        let measured = Counted::<false>::new(150);
+        let mut bytes = Vec::new();
+        encode_message_prefixed(2, &measured, &mut bytes).unwrap();
+        assert_eq!(bytes, [0x12, 0x03, 0x08, 0x96, 0x01]);
+        assert_eq!(measured.writes.get(), 1);
+    }
+
+    #[test]
+    fn present_empty_string_and_bytes_are_written() {
+        let mut bytes = Vec::new();
+        encode_string(1, "", &mut bytes).unwrap();
+        assert!(bytes.is_empty());
+
+        encode_string_present(1, "", &mut bytes).unwrap();
+        assert_eq!(bytes, [0x0A, 0x00]);
+        assert_eq!(
+            decoder::decode_string_field(1, &mut &bytes[..]).unwrap(),
+            Some(String::new())
+        );
+
+        let mut bytes = Vec::new();
+        encode_bytes_present(2, &[], &mut bytes).unwrap();
+        assert_eq!(bytes, [0x12, 0x00]);
+        assert_eq!(
+            decoder::decode_bytes_field(2, &mut &bytes[..]).unwrap(),
+            Some(Vec::new())
+        );
+    }
+
+    #[test]
+    fn present_false_bool_is_written() {
+        let mut bytes = Vec::new();
+        encode_bool(3, false, &mut bytes).unwrap();
+        assert!(bytes.is_empty());
+
+        encode_bool_present(3, false, &mut bytes).unwrap();
+        assert_eq!(bytes, [0x18, 0x00]);
+        assert_eq!(
+            decoder::decode_bool_field(3, &mut &bytes[..]).unwrap(),
+            Some(false)
+        );
+    }
+}
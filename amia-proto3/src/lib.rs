@@ -1,21 +1,261 @@
 pub mod builder;
 pub mod decoder;
 pub mod encoder;
+pub mod prelude;
+
+#[cfg(feature = "test-util")]
+pub mod test_util;
+
+#[cfg(test)]
+mod test_support;
 
 use std::io::{self, Cursor, Read, Write};
 
 pub trait Protobuf: Sized + Default {
     fn encode_to_writer<W: Write>(&self, writer: &mut W) -> io::Result<()>;
-    // fn encoded_len(&self) -> usize;
+    /// Set to `true` alongside an `encoded_len` override that does not serialize, so that
+    /// length-prefixing encoders can stream the message instead of buffering it first.
+    const CHEAP_ENCODED_LEN: bool = false;
+    /// Override with the `encoder::size_of_*` helpers; the default serializes to measure.
+    fn encoded_len(&self) -> usize {
+        self.encode_to_vec().len()
+    }
     fn encode_to_vec(&self) -> Vec<u8> {
         let mut buffer = Vec::new();
         self.encode_to_writer(&mut buffer).unwrap();
         buffer
     }
+    fn encode_length_delimited_to_writer<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        if Self::CHEAP_ENCODED_LEN {
+            encoder::encode_varint(self.encoded_len() as u64, writer)?;
+            return self.encode_to_writer(writer);
+        }
+        let encoded = self.encode_to_vec();
+        encoder::encode_varint(encoded.len() as u64, writer)?;
+        writer.write_all(&encoded)
+    }
+    fn encode_length_delimited_to_vec(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        self.encode_length_delimited_to_writer(&mut buffer).unwrap();
+        buffer
+    }
+    fn encode_framed<W: Write>(&self, prefix: LengthPrefix, writer: &mut W) -> io::Result<()> {
+        if Self::CHEAP_ENCODED_LEN {
+            prefix.write_length(self.encoded_len(), writer)?;
+            return self.encode_to_writer(writer);
+        }
+        let encoded = self.encode_to_vec();
+        prefix.write_length(encoded.len(), writer)?;
+        writer.write_all(&encoded)
+    }
+    /// FNV-1a 64 of the encoded bytes, identical across runs and platforms.
+    ///
+    /// Only as stable as `encode_to_writer` is deterministic: messages holding a `HashMap`
+    /// must write map entries in a fixed order (e.g. via `ProtobufBuilder::build_canonical`).
+    fn content_hash(&self) -> u64 {
+        let mut hasher = Fnv1a64::default();
+        self.encode_to_writer(&mut hasher).unwrap();
+        hasher.0
+    }
+    /// Reads fields until the reader is exhausted or a zero tag is found.
+    ///
+    /// EOF between fields ends the message successfully; EOF inside a field's
+    /// tag or value is reported as [`DecodeError::UnexpectedEof`].
     fn decode_from_reader<R: Read>(reader: &mut R) -> DecodeResult<Self>;
     fn decode_from_slice(bytes: &[u8]) -> DecodeResult<Self> {
         let mut cursor = Cursor::new(bytes);
-        Self::decode_from_reader(&mut cursor)
+        Self::decode_from_cursor(&mut cursor)
+    }
+    /// Decodes from the cursor's current position, leaving it just past the last byte read.
+    ///
+    /// Decoding stops at a zero tag or the end of the data, so anything that follows the
+    /// message in the same buffer must be separated by a zero tag to be left unread.
+    fn decode_from_cursor(cursor: &mut Cursor<&[u8]>) -> DecodeResult<Self> {
+        Self::decode_from_reader(cursor).map_err(|err| DecodeError::At {
+            offset: cursor.position() as usize,
+            source: Box::new(err),
+        })
+    }
+    /// Like [`Protobuf::decode_from_slice`], but fails if any bytes remain after the message.
+    fn decode_exact(bytes: &[u8]) -> DecodeResult<Self> {
+        let mut cursor = Cursor::new(bytes);
+        let message = Self::decode_from_cursor(&mut cursor)?;
+        let offset = cursor.position() as usize;
+        if offset != bytes.len() {
+            return Err(DecodeError::At {
+                offset,
+                source: Box::new(DecodeError::MalformedInput(format!(
+                    "{} trailing bytes after message",
+                    bytes.len() - offset
+                ))),
+            });
+        }
+        Ok(message)
+    }
+    fn decode_framed<R: Read>(prefix: LengthPrefix, reader: &mut R) -> DecodeResult<Self> {
+        let length = prefix.read_length(reader)?;
+        let mut limited = reader.take(length);
+        let message = Self::decode_from_reader(&mut limited)?;
+        if limited.limit() != 0 {
+            return Err(DecodeError::MalformedInput(format!(
+                "Framed message consumed {} of {} bytes",
+                length - limited.limit(),
+                length
+            )));
+        }
+        Ok(message)
+    }
+    fn decode_into<R: Read>(&mut self, reader: &mut R) -> DecodeResult<()> {
+        self.clear();
+        self.merge_from_reader(reader)
+    }
+    /// Merges the decoded fields into `self`. The default replaces `self` outright;
+    /// override it to append repeated fields and keep existing allocations.
+    fn merge_from_reader<R: Read>(&mut self, reader: &mut R) -> DecodeResult<()> {
+        *self = Self::decode_from_reader(reader)?;
+        Ok(())
+    }
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthPrefix {
+    Varint,
+    Fixed32Be,
+    Fixed32Le,
+}
+
+impl LengthPrefix {
+    pub fn write_length<W: Write + ?Sized>(self, length: usize, writer: &mut W) -> io::Result<()> {
+        let fixed = || {
+            u32::try_from(length).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Message too long for a 32-bit length prefix",
+                )
+            })
+        };
+        match self {
+            LengthPrefix::Varint => encoder::encode_varint(length as u64, writer),
+            LengthPrefix::Fixed32Be => writer.write_all(&fixed()?.to_be_bytes()),
+            LengthPrefix::Fixed32Le => writer.write_all(&fixed()?.to_le_bytes()),
+        }
+    }
+
+    pub fn read_length<R: Read>(self, reader: &mut R) -> DecodeResult<u64> {
+        match self {
+            LengthPrefix::Varint => decoder::decode_varint(reader),
+            LengthPrefix::Fixed32Be => Ok(decoder::decode_fixed32_be(reader)? as u64),
+            LengthPrefix::Fixed32Le => Ok(decoder::decode_fixed32(reader)? as u64),
+        }
+    }
+}
+
+/// How `ProtobufBuilder` writes singular scalar fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeOptions {
+    /// Omit fields holding their proto3 default (`0`, `false`, `""`, empty bytes). Turning
+    /// this off puts every field on the wire, e.g. for decoder test fixtures.
+    pub skip_defaults: bool,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            skip_defaults: true,
+        }
+    }
+}
+
+struct Fnv1a64(u64);
+
+impl Default for Fnv1a64 {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Write for Fnv1a64 {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FieldNumber(u32);
+
+impl FieldNumber {
+    pub const MAX: u32 = (1 << 29) - 1;
+    pub const RESERVED_START: u32 = 19000;
+    pub const RESERVED_END: u32 = 19999;
+
+    pub const fn new(value: u32) -> Option<Self> {
+        if value == 0
+            || value > Self::MAX
+            || (value >= Self::RESERVED_START && value <= Self::RESERVED_END)
+        {
+            return None;
+        }
+        Some(Self(value))
+    }
+
+    pub const fn get(self) -> u32 {
+        self.0
+    }
+}
+
+impl From<FieldNumber> for u32 {
+    fn from(field_number: FieldNumber) -> Self {
+        field_number.0
+    }
+}
+
+impl TryFrom<u32> for FieldNumber {
+    type Error = InvalidFieldNumber;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        Self::new(value).ok_or(InvalidFieldNumber(value))
+    }
+}
+
+/// Returned when a `u32` is zero, in the reserved range, or above [`FieldNumber::MAX`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidFieldNumber(pub u32);
+
+impl std::fmt::Display for InvalidFieldNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "invalid field number {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidFieldNumber {}
+
+/// Field numbers accepted by the encoders and the builder: a bare `u32`, or a
+/// [`FieldNumber`] validated up front.
+pub trait IntoFieldNumber: Copy {
+    fn into_field_number(self) -> u32;
+}
+
+impl IntoFieldNumber for u32 {
+    #[inline]
+    fn into_field_number(self) -> u32 {
+        self
+    }
+}
+
+impl IntoFieldNumber for FieldNumber {
+    #[inline]
+    fn into_field_number(self) -> u32 {
+        self.0
     }
 }
 
@@ -31,21 +271,126 @@ pub enum WireType {
     Fixed32 = 5,
 }
 
+impl WireType {
+    pub const fn as_u32(self) -> u32 {
+        self as u32
+    }
+
+    pub const fn fixed_width(&self) -> Option<usize> {
+        match self {
+            WireType::Fixed32 => Some(4),
+            WireType::Fixed64 => Some(8),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for WireType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}({})", self, self.as_u32())
+    }
+}
+
+impl TryFrom<u8> for WireType {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(WireType::Varint),
+            1 => Ok(WireType::Fixed64),
+            2 => Ok(WireType::LengthDelimited),
+            // 3 => Ok(WireType::StartGroup),
+            // 4 => Ok(WireType::EndGroup),
+            5 => Ok(WireType::Fixed32),
+            _ => Err(DecodeError::InvalidWireType(value as u32)),
+        }
+    }
+}
+
+impl TryFrom<u32> for WireType {
+    type Error = DecodeError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        u8::try_from(value)
+            .map_err(|_| DecodeError::InvalidWireType(value))
+            .and_then(WireType::try_from)
+    }
+}
+
 #[derive(Debug)]
 pub enum DecodeError {
     IoError(io::Error),
     UnexpectedEof,
     InvalidWireType(u32),
     InvalidTag,
-    InvalidVarint,
+    InvalidVarint {
+        consumed: usize,
+        partial_value: u64,
+    },
     InvalidUtf8(std::string::FromUtf8Error),
-    UnexpectedWireType { expected: WireType, got: WireType },
+    UnexpectedWireType {
+        expected: WireType,
+        got: WireType,
+    },
     MalformedInput(String),
+    At {
+        offset: usize,
+        source: Box<DecodeError>,
+    },
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DecodeError::InvalidWireType(value) => write!(f, "Invalid wire type {}", value),
+            DecodeError::UnexpectedWireType { expected, got } => {
+                write!(
+                    f,
+                    "Unexpected wire type: expected {}, got {}",
+                    expected, got
+                )
+            }
+            DecodeError::At { offset, source } => write!(f, "at offset {}: {}", offset, source),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecodeError::At { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl DecodeError {
+    fn io_kind(&self) -> io::ErrorKind {
+        match self {
+            DecodeError::IoError(err) => err.kind(),
+            DecodeError::UnexpectedEof => io::ErrorKind::UnexpectedEof,
+            DecodeError::At { source, .. } => source.io_kind(),
+            _ => io::ErrorKind::InvalidData,
+        }
+    }
+}
+
+impl From<DecodeError> for io::Error {
+    fn from(err: DecodeError) -> Self {
+        match err {
+            DecodeError::IoError(err) => err,
+            _ => io::Error::new(err.io_kind(), err),
+        }
+    }
 }
 
 impl From<io::Error> for DecodeError {
     fn from(err: io::Error) -> Self {
-        DecodeError::IoError(err)
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => DecodeError::UnexpectedEof,
+            _ => DecodeError::IoError(err),
+        }
     }
 }
 
@@ -56,3 +401,152 @@ impl From<std::string::FromUtf8Error> for DecodeError {
 }
 
 pub type DecodeResult<T> = Result<T, DecodeError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{Counted, Sample};
+
+    #[test]
+    fn decode_from_reader_eof_contract() {
+        let sample = Sample {
+            score: 1.5,
+            ..Sample::new(300, "amia").with_child(Sample::new(7, "child"))
+        };
+        let bytes = sample.encode_to_vec();
+
+        // Offsets where each top-level field ends.
+        let mut boundaries = vec![0];
+        let mut end = 0;
+        for field in [
+            Sample::new(300, ""),
+            Sample::new(0, "amia"),
+            Sample {
+                score: 1.5,
+                ..Sample::default()
+            },
+            Sample::default().with_child(Sample::new(7, "child")),
+        ] {
+            end += field.encode_to_vec().len();
+            boundaries.push(end);
+        }
+        assert_eq!(end, bytes.len());
+
+        for cut in 0..=bytes.len() {
+            let result = Sample::decode_from_reader(&mut &bytes[..cut]);
+            if boundaries.contains(&cut) {
+                assert!(
+                    result.is_ok(),
+                    "cut at field boundary {}: {:?}",
+                    cut,
+                    result
+                );
+            } else {
+                assert!(
+                    matches!(result, Err(DecodeError::UnexpectedEof)),
+                    "cut inside a field at {}: {:?}",
+                    cut,
+                    result
+                );
+            }
+        }
+
+        assert_eq!(Sample::decode_from_reader(&mut &bytes[..]).unwrap(), sample);
+        assert_eq!(
+            Sample::decode_from_reader(&mut &bytes[..boundaries[2]]).unwrap(),
+            Sample::new(300, "amia")
+        );
+    }
+
+    #[test]
+    fn field_number_try_from_reports_rejected_value() {
+        assert_eq!(FieldNumber::try_from(7), Ok(FieldNumber::new(7).unwrap()));
+        assert_eq!(FieldNumber::try_from(0), Err(InvalidFieldNumber(0)));
+        assert_eq!(FieldNumber::try_from(19000), Err(InvalidFieldNumber(19000)));
+        assert_eq!(
+            FieldNumber::try_from(FieldNumber::MAX + 1),
+            Err(InvalidFieldNumber(FieldNumber::MAX + 1))
+        );
+    }
+
+    #[test]
+    fn typed_field_numbers_encode_like_bare_u32() {
+        let field = FieldNumber::new(3).unwrap();
+
+        let mut typed = Vec::new();
+        encoder::encode_uint32(field, 150, &mut typed).unwrap();
+        encoder::encode_string(field, "hi", &mut typed).unwrap();
+        let mut bare = Vec::new();
+        encoder::encode_uint32(3, 150, &mut bare).unwrap();
+        encoder::encode_string(3, "hi", &mut bare).unwrap();
+        assert_eq!(typed, bare);
+
+        let typed = builder::ProtobufBuilder::new()
+            .add_uint32(field, 150)
+            .add_packed_double(FieldNumber::new(4).unwrap(), &[1.0])
+            .build();
+        let bare = builder::ProtobufBuilder::new()
+            .add_uint32(3, 150)
+            .add_packed_double(4, &[1.0])
+            .build();
+        assert_eq!(typed, bare);
+    }
+
+    #[test]
+    fn length_delimited_serializes_once() {
+        let expected = [0x03, 0x08, 0x96, 0x01];
+
+        let cheap = Counted::<true>::new(150);
+        assert_eq!(cheap.encode_length_delimited_to_vec(), expected);
+        assert_eq!(cheap.writes.get(), 1);
+
+        let measured = Counted::<false>::new(150);
+        assert_eq!(measured.encode_length_delimited_to_vec(), expected);
+        assert_eq!(measured.writes.get(), 1);
+    }
+
+    #[test]
+    fn framed_round_trips_with_each_prefix() {
+        let sample = Sample::new(300, "amia").with_child(Sample::new(7, "child"));
+        let body = sample.encode_to_vec();
+
+        for prefix in [
+            LengthPrefix::Varint,
+            LengthPrefix::Fixed32Be,
+            LengthPrefix::Fixed32Le,
+        ] {
+            let mut framed = Vec::new();
+            sample.encode_framed(prefix, &mut framed).unwrap();
+            // A second frame must be left untouched by the first decode.
+            sample.encode_framed(prefix, &mut framed).unwrap();
+
+            let mut length = Vec::new();
+            prefix.write_length(body.len(), &mut length).unwrap();
+            assert_eq!(&framed[..length.len()], &length[..], "{:?}", prefix);
+
+            let mut reader = &framed[..];
+            assert_eq!(Sample::decode_framed(prefix, &mut reader).unwrap(), sample);
+            assert_eq!(Sample::decode_framed(prefix, &mut reader).unwrap(), sample);
+            assert!(reader.is_empty());
+        }
+
+        let measured = Counted::<false>::new(150);
+        measured
+            .encode_framed(LengthPrefix::Fixed32Be, &mut Vec::new())
+            .unwrap();
+        assert_eq!(measured.writes.get(), 1);
+    }
+
+    #[test]
+    fn at_error_displays_offset_and_chains_source() {
+        use std::error::Error as _;
+
+        let err = DecodeError::At {
+            offset: 3,
+            source: Box::new(DecodeError::InvalidWireType(7)),
+        };
+        assert_eq!(err.to_string(), "at offset 3: Invalid wire type 7");
+        assert_eq!(err.source().unwrap().to_string(), "Invalid wire type 7");
+        assert!(DecodeError::InvalidWireType(7).source().is_none());
+    }
+}
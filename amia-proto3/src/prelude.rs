@@ -0,0 +1,7 @@
+pub use crate::builder::ProtobufBuilder;
+pub use crate::decoder::*;
+pub use crate::encoder::*;
+pub use crate::{
+    DecodeError, DecodeResult, EncodeOptions, FieldNumber, IntoFieldNumber, InvalidFieldNumber,
+    LengthPrefix, Protobuf, WireType,
+};
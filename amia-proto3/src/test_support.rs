@@ -0,0 +1,98 @@
+use crate::decoder::{self, Tag};
+use crate::{DecodeResult, Protobuf, encoder};
+use std::cell::Cell;
+use std::io::{self, Read, Write};
+
+// A small hand-written message shared by the crate's tests.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct Sample {
+    pub id: u32,
+    pub name: String,
+    pub score: f64,
+    pub child: Option<Box<Sample>>,
+}
+
+impl Sample {
+    pub fn new(id: u32, name: &str) -> Self {
+        Self {
+            id,
+            name: name.to_string(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_child(mut self, child: Sample) -> Self {
+        self.child = Some(Box::new(child));
+        self
+    }
+}
+
+impl Protobuf for Sample {
+    fn encode_to_writer<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        encoder::encode_uint32(1, self.id, writer)?;
+        encoder::encode_string(2, &self.name, writer)?;
+        encoder::encode_double(3, self.score, writer)?;
+        if let Some(child) = &self.child {
+            encoder::encode_message(4, child.as_ref(), writer)?;
+        }
+        Ok(())
+    }
+
+    fn decode_from_reader<R: Read>(reader: &mut R) -> DecodeResult<Self> {
+        let mut message = Self::default();
+        while let Some(tag) = Tag::decode(reader)? {
+            match tag.field_number {
+                1 => message.id = decoder::decode_uint32(reader)?,
+                2 => message.name = decoder::decode_string(reader)?,
+                3 => message.score = decoder::decode_double(reader)?,
+                4 => message.child = Some(Box::new(decoder::decode_message(reader)?)),
+                _ => decoder::skip_field(tag.wire_type, reader)?,
+            }
+        }
+        Ok(message)
+    }
+}
+
+// Counts serializations; `CHEAP` selects whether `encoded_len` is computed or measured.
+#[derive(Debug, Default)]
+pub(crate) struct Counted<const CHEAP: bool> {
+    pub value: u32,
+    pub writes: Cell<usize>,
+}
+
+impl<const CHEAP: bool> Counted<CHEAP> {
+    pub fn new(value: u32) -> Self {
+        Self {
+            value,
+            writes: Cell::new(0),
+        }
+    }
+}
+
+impl<const CHEAP: bool> Protobuf for Counted<CHEAP> {
+    const CHEAP_ENCODED_LEN: bool = CHEAP;
+
+    fn encode_to_writer<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.writes.set(self.writes.get() + 1);
+        encoder::encode_uint32(1, self.value, writer)
+    }
+
+    fn encoded_len(&self) -> usize {
+        if CHEAP {
+            encoder::size_of_uint32(1, self.value)
+        } else {
+            self.encode_to_vec().len()
+        }
+    }
+
+    fn decode_from_reader<R: Read>(reader: &mut R) -> DecodeResult<Self> {
+        let mut message = Self::default();
+        while let Some(tag) = Tag::decode(reader)? {
+            match tag.field_number {
+                1 => message.value = decoder::decode_uint32(reader)?,
+                _ => decoder::skip_field(tag.wire_type, reader)?,
+            }
+        }
+        Ok(message)
+    }
+}
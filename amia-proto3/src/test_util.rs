@@ -0,0 +1,48 @@
+use crate::Protobuf;
+use std::fmt::Write;
+
+/// Panics with a hex dump of both encodings if `a` and `b` do not encode to the same bytes.
+///
+/// Byte equality has the same determinism requirement as [`Protobuf::content_hash`].
+#[track_caller]
+pub fn assert_encoded_eq<A: Protobuf, B: Protobuf>(a: &A, b: &B) {
+    let left = a.encode_to_vec();
+    let right = b.encode_to_vec();
+    if left == right {
+        return;
+    }
+
+    let first_diff = left
+        .iter()
+        .zip(&right)
+        .position(|(l, r)| l != r)
+        .unwrap_or(left.len().min(right.len()));
+
+    panic!(
+        "encodings differ at byte {} (left {} bytes, right {} bytes)\n left: {}\nright: {}",
+        first_diff,
+        left.len(),
+        right.len(),
+        hex_marked(&left, first_diff),
+        hex_marked(&right, first_diff),
+    );
+}
+
+// Hex bytes separated by spaces, with the first differing byte wrapped in brackets.
+fn hex_marked(bytes: &[u8], mark: usize) -> String {
+    let mut out = String::with_capacity(bytes.len() * 3 + 2);
+    for (i, byte) in bytes.iter().enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        if i == mark {
+            write!(out, "[{:02x}]", byte).unwrap();
+        } else {
+            write!(out, "{:02x}", byte).unwrap();
+        }
+    }
+    if mark >= bytes.len() {
+        out.push_str(" []");
+    }
+    out
+}